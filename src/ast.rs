@@ -1,22 +1,41 @@
 
+use std::cell::Cell;
+
 pub type Program = Vec<Statement>;
 
 #[derive(Debug, PartialEq, Clone)]
 pub struct Function {
     pub name: String,
+
+    // NOTE: For `function a.b.c()` the name is `a` and the path is `b, c`. A
+    //       method, `function a:m()`, is the same as `a.m` with a `self`
+    //       parameter prepended.
+    pub path: Vec<String>,
+
+    // NOTE: A variadic function's last parameter is `...`.
     pub parameters: Vec<String>,
     pub body: Vec<Statement>,
 }
 
 #[derive(Debug, PartialEq, Clone)]
 pub enum Statement {
-    Assignment(Box<Expression>, Box<Expression>),
-    Return(Box<Expression>),
-    Local(String, Box<Expression>),
+    Assignment(Vec<Box<Expression>>, Vec<Box<Expression>>),
+    Return(Vec<Box<Expression>>),
+    Local(Vec<(String, Option<Attribute>)>, Vec<Box<Expression>>),
     Expression(Box<Expression>),
     Function(Function),
+    LocalFunction(Function),
+    Do(Vec<Statement>),
     If(Box<Expression>, Vec<Statement>, Vec<(Box<Expression>, Vec<Statement>)>, Option<Vec<Statement>>),
     NumericFor(String, Box<Expression>, Box<Expression>, Option<Box<Expression>>, Vec<Statement>),
+    GenericFor(Vec<String>, Vec<Box<Expression>>, Vec<Statement>),
+    Repeat(Vec<Statement>, Box<Expression>),
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum Attribute {
+    Const,
+    Close,
 }
 
 #[derive(Debug, PartialEq, Clone)]
@@ -25,30 +44,57 @@ pub enum Operation {
     Subtract,
     Multiply,
     Divide,
+    Modulo,
+    Power,
+    Concat,
 
     Equals,
+    NotEquals,
     GraterThan,
     LessThan,
     GraterThanEquals,
     LessThanEquals,
+
+    And,
+    Or,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum UnaryOperation {
+    Negate,
+    Length,
 }
 
 #[derive(Debug, PartialEq, Clone)]
 pub enum Expression {
     Term(Term),
     Binary(Box<Expression>, Operation, Box<Expression>),
-    Call(Box<Expression>, Vec<Box<Expression>>),
+    Unary(UnaryOperation, Box<Expression>),
+
+    // NOTE: Calls keep the line they're on, which `error` adds to messages.
+    Call(Box<Expression>, Vec<Box<Expression>>, usize),
+    MethodCall(Box<Expression>, String, Vec<Box<Expression>>, usize),
     Dot(Box<Expression>, String),
     Index(Box<Expression>, Box<Expression>),
     Function(Vec<String>, Vec<Statement>),
+
+    // NOTE: Parentheses truncate a multiple value expression, such as
+    //       a call or `...`, to its first value.
+    Parenthesised(Box<Expression>),
 }
 
 #[derive(Debug, PartialEq, Clone)]
 pub enum Term {
+    Nil,
     Number(f64),
     String(String),
     Boolean(bool),
-    Variable(String),
+
+    // NOTE: The flag is set when checking, where the variable is used by a
+    //       closure before a local of the same name is declared in the same
+    //       function, so it refers to the global instead.
+    Variable(String, Cell<bool>),
+    Vararg,
 
     Table(Vec<(Option<TableConstructionIndex>, Box<Expression>)>),
 }
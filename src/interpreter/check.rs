@@ -0,0 +1,267 @@
+use std::cell::Cell;
+use std::collections::HashMap;
+use std::mem;
+
+use crate::ast::{Program, Statement, Expression, Term, TableConstructionIndex};
+
+use super::error::LuaError;
+use super::Result;
+
+/// Checks a program for errors that are reported before it's run, which are
+/// assignments to locals declared with an attribute and using `...` outside
+/// of a variadic function. Variables used by a closure that a local declared
+/// after the closure shares the name of, which are globals in the closure,
+/// are flagged.
+pub fn check_program(program: &Program) -> Result<()> {
+    // NOTE: The main chunk is always variadic.
+    let mut checker = Checker {
+        blocks: Vec::new(),
+        free_names: HashMap::new(),
+        is_variadic: true,
+    };
+
+    checker.check_block(&[], program)
+}
+
+// The uses of each name, by the flag of each variable reading it. Assigning
+// to a name uses it without a flag.
+type Uses<'a> = HashMap<String, Vec<&'a Cell<bool>>>;
+
+fn add_uses<'a>(uses: &mut Uses<'a>, other: Uses<'a>) {
+    for (name, flags) in other {
+        uses.entry(name).or_default().extend(flags);
+    }
+}
+
+#[derive(Default)]
+struct Block<'a> {
+    // The locals declared in the block, in the order they were declared, and
+    // whether they're constant.
+    locals: Vec<(String, bool)>,
+
+    // Names used by closures created in the block, or any nested block, that
+    // didn't refer to a local when the closure was created.
+    captured_names: Uses<'a>,
+}
+
+struct Checker<'a> {
+    blocks: Vec<Block<'a>>,
+
+    // Names used in the innermost enclosing function that don't refer to a
+    // local, so are globals.
+    free_names: Uses<'a>,
+
+    // Whether the innermost enclosing function is variadic.
+    is_variadic: bool,
+}
+
+impl<'a> Checker<'a> {
+    fn is_local(&self, name: &str) -> bool {
+        self.blocks.iter().any(|block| block.locals.iter().any(|(local, _)| local == name))
+    }
+
+    fn reference(&mut self, name: &str, flag: Option<&'a Cell<bool>>) {
+        if !self.is_local(name) {
+            self.free_names.entry(name.to_owned()).or_default().extend(flag);
+        }
+    }
+
+    fn declare(&mut self, name: &str, is_const: bool) {
+        if let Some(block) = self.blocks.last_mut() {
+            block.locals.push((name.to_owned(), is_const));
+        }
+    }
+
+    // NOTE: A closure that uses a local declared after it was created, in
+    //       the same function, gets the global of that name instead. That's
+    //       valid, but if the global is nil it's most likely a mistake, so
+    //       errors from using it say so when running.
+    fn declare_local(&mut self, name: &str, is_const: bool) {
+        let flags = self.blocks.last().and_then(|block| block.captured_names.get(name));
+        for flag in flags.into_iter().flatten() {
+            flag.set(true);
+        }
+
+        self.declare(name, is_const);
+    }
+
+    fn check_assign_to(&mut self, name: &str) -> Result<()> {
+        self.reference(name, None);
+        let is_const = self.blocks.iter().rev()
+            .flat_map(|block| block.locals.iter().rev())
+            .find(|(local, _)| local == name)
+            .is_some_and(|(_, is_const)| *is_const);
+
+        if is_const {
+            Err(LuaError::AssignToConst(name.to_owned()))
+        } else {
+            Ok(())
+        }
+    }
+
+    fn check_function(&mut self, parameters: &[String], body: &'a [Statement]) -> Result<()> {
+        let is_variadic = parameters.last().is_some_and(|parameter| parameter == "...");
+        let enclosing_is_variadic = mem::replace(&mut self.is_variadic, is_variadic);
+        let enclosing_free_names = mem::take(&mut self.free_names);
+
+        let result = self.check_block(parameters, body);
+
+        // NOTE: Names that are free in the function are also free in the
+        //       enclosing one, as they didn't refer to any of its locals.
+        let free_names = mem::replace(&mut self.free_names, enclosing_free_names);
+        self.is_variadic = enclosing_is_variadic;
+        if let Some(block) = self.blocks.last_mut() {
+            add_uses(&mut block.captured_names, free_names.clone());
+        }
+        add_uses(&mut self.free_names, free_names);
+        result
+    }
+
+    fn check_block(&mut self, locals: &[String], body: &'a [Statement]) -> Result<()> {
+        self.blocks.push(Block::default());
+        for local in locals {
+            self.declare(local, false);
+        }
+
+        let result = body.iter().try_for_each(|statement| self.check_statement(statement));
+        self.end_block();
+        result
+    }
+
+    fn end_block(&mut self) {
+        let block = self.blocks.pop().unwrap_or_default();
+        if let Some(enclosing) = self.blocks.last_mut() {
+            add_uses(&mut enclosing.captured_names, block.captured_names);
+        }
+    }
+
+    fn check_statement(&mut self, statement: &'a Statement) -> Result<()> {
+        match statement {
+            Statement::Assignment(targets, values) => {
+                values.iter().try_for_each(|value| self.check_expression(value))?;
+                targets.iter().try_for_each(|target| match target.as_ref() {
+                    Expression::Term(Term::Variable(name, _)) => self.check_assign_to(name),
+                    _ => self.check_expression(target),
+                })
+            },
+
+            Statement::Return(values) =>
+                values.iter().try_for_each(|value| self.check_expression(value)),
+
+            Statement::Expression(value) =>
+                self.check_expression(value),
+
+            // NOTE: Both `<const>` and `<close>` locals are read only.
+            Statement::Local(names, values) => {
+                values.iter().try_for_each(|value| self.check_expression(value))?;
+                for (name, attribute) in names {
+                    self.declare_local(name, attribute.is_some());
+                }
+                Ok(())
+            },
+
+            Statement::Function(function) => {
+                if function.path.is_empty() {
+                    self.check_assign_to(&function.name)?;
+                }
+                self.check_function(&function.parameters, &function.body)
+            },
+
+            // NOTE: The name is declared first, so the function can refer to
+            //       itself within its body.
+            Statement::LocalFunction(function) => {
+                self.declare_local(&function.name, false);
+                self.check_function(&function.parameters, &function.body)
+            },
+
+            Statement::Do(body) => self.check_block(&[], body),
+
+            Statement::If(condition, then, elseif, else_) => {
+                self.check_expression(condition)?;
+                self.check_block(&[], then)?;
+                for (condition, then) in elseif {
+                    self.check_expression(condition)?;
+                    self.check_block(&[], then)?;
+                }
+
+                match else_ {
+                    Some(body) => self.check_block(&[], body),
+                    None => Ok(()),
+                }
+            },
+
+            Statement::NumericFor(name, initial_value, limit, step, body) => {
+                self.check_expression(initial_value)?;
+                self.check_expression(limit)?;
+                if let Some(step) = step {
+                    self.check_expression(step)?;
+                }
+
+                self.check_block(std::slice::from_ref(name), body)
+            },
+
+            Statement::GenericFor(names, values, body) => {
+                values.iter().try_for_each(|value| self.check_expression(value))?;
+                self.check_block(names, body)
+            },
+
+            // NOTE: The condition is part of the body's block, as it can use
+            //       the locals declared there.
+            Statement::Repeat(body, condition) => {
+                self.blocks.push(Block::default());
+                let result = body.iter()
+                    .try_for_each(|statement| self.check_statement(statement))
+                    .and_then(|_| self.check_expression(condition));
+                self.end_block();
+                result
+            },
+        }
+    }
+
+    fn check_expression(&mut self, expression: &'a Expression) -> Result<()> {
+        match expression {
+            Expression::Term(Term::Table(items)) => {
+                for (index, value) in items {
+                    if let Some(TableConstructionIndex::Value(index)) = index {
+                        self.check_expression(index)?;
+                    }
+                    self.check_expression(value)?;
+                }
+                Ok(())
+            },
+
+            Expression::Term(Term::Vararg) if !self.is_variadic =>
+                Err(LuaError::VarargOutsideVarargFunction),
+
+            Expression::Term(Term::Variable(name, declared_after_use)) => {
+                self.reference(name, Some(declared_after_use));
+                Ok(())
+            },
+
+            Expression::Term(_) => Ok(()),
+
+            Expression::Binary(lhs, _, rhs) | Expression::Index(lhs, rhs) => {
+                self.check_expression(lhs)?;
+                self.check_expression(rhs)
+            },
+
+            Expression::Unary(_, operand)
+                | Expression::Dot(operand, _)
+                | Expression::Parenthesised(operand) =>
+                self.check_expression(operand),
+
+            Expression::Call(callee, arguments, _) => {
+                self.check_expression(callee)?;
+                arguments.iter().try_for_each(|argument| self.check_expression(argument))
+            },
+
+            Expression::MethodCall(receiver, _, arguments, _) => {
+                self.check_expression(receiver)?;
+                arguments.iter().try_for_each(|argument| self.check_expression(argument))
+            },
+
+            Expression::Function(parameters, body) =>
+                self.check_function(parameters, body),
+        }
+    }
+}
@@ -1,27 +1,103 @@
 use core::fmt;
 use std::error::Error;
+use lalrpop_util::lexer::Token;
 
 use super::Value;
 
+#[derive(PartialEq, Debug)]
+pub struct ParseError {
+    pub message: String,
+    pub line: usize,
+    pub column: usize,
+}
+
+impl ParseError {
+    pub fn new(source: &str, error: lalrpop_util::ParseError<usize, Token<'_>, (usize, &str)>) -> Self {
+        let (location, message) = match error {
+            lalrpop_util::ParseError::InvalidToken { location } =>
+                (location, "invalid token".to_owned()),
+
+            lalrpop_util::ParseError::UnrecognizedEof { location, expected } =>
+                (location, format!("unexpected end of file{}", format_expected(&expected))),
+
+            lalrpop_util::ParseError::UnrecognizedToken { token: (location, token, _), expected } =>
+                (location, format!("unexpected '{}'{}", token, format_expected(&expected))),
+
+            lalrpop_util::ParseError::ExtraToken { token: (location, token, _) } =>
+                (location, format!("unexpected '{}'", token)),
+
+            lalrpop_util::ParseError::User { error: (location, message) } =>
+                (location, message.to_owned()),
+        };
+
+        let preceding = &source[..location];
+        let line = preceding.matches('\n').count() + 1;
+        let column = preceding.chars().rev().take_while(|c| *c != '\n').count() + 1;
+        ParseError { message, line, column }
+    }
+}
+
+fn format_expected(expected: &[String]) -> String {
+    if expected.is_empty() {
+        String::new()
+    } else {
+        format!(", expected one of {}", expected.join(", "))
+    }
+}
+
 #[derive(PartialEq, Debug)]
 pub enum LuaError {
+    ParseError(ParseError),
     InvalidIndex(Value),
     InvalidCall(Value),
     InvalidArithmetic(Value),
+    InvalidConcat(Value),
+    InvalidLength(Value),
+    InvalidTableIndex(Value),
+    InvalidComparison(Value, Value),
     BadForLimit(Value),
     BadForInitialValue(Value),
     BadForStep(Value),
+    ForStepIsZero,
+    AssignToConst(String),
+    VarargOutsideVarargFunction,
+    UpvalueBeforeDeclaration(String),
+    NonClosableValue(String),
+    BadArgument(usize, &'static str, String),
+    Custom(String),
+
+    // An error raised by a script, with any value as its message.
+    Runtime(Value),
 }
 
 impl fmt::Display for LuaError {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
+            Self::ParseError(e) => write!(f, "{}:{}: {}", e.line, e.column, e.message),
             Self::InvalidIndex(v) => write!(f, "attempt to index a {} value", v.type_name()),
             Self::InvalidCall(v) => write!(f, "attempt to call a {} value", v.type_name()),
             Self::InvalidArithmetic(v) => write!(f, "attempt to perform arithmetic on a {} value", v.type_name()),
+            Self::InvalidConcat(v) => write!(f, "attempt to concatenate a {} value", v.type_name()),
+            Self::InvalidLength(v) => write!(f, "attempt to get length of a {} value", v.type_name()),
+            Self::InvalidTableIndex(Value::Nil) => write!(f, "index is nil"),
+            Self::InvalidTableIndex(_) => write!(f, "index is NaN"),
+            Self::InvalidComparison(a, b) if a.type_name() == b.type_name() =>
+                write!(f, "attempt to compare two {} values", a.type_name()),
+            Self::InvalidComparison(a, b) =>
+                write!(f, "attempt to compare {} with {}", a.type_name(), b.type_name()),
             Self::BadForLimit(v) => write!(f, "bad 'for' limit (number expected, got {})", v.type_name()),
             Self::BadForInitialValue(v) => write!(f, "bad 'for' initial value (number expected, got {})", v.type_name()),
             Self::BadForStep(v) => write!(f, "bad 'for' step (number expected, got {})", v.type_name()),
+            Self::ForStepIsZero => write!(f, "'for' step is zero"),
+            Self::AssignToConst(name) => write!(f, "attempt to assign to const variable '{}'", name),
+            Self::VarargOutsideVarargFunction => write!(f, "cannot use '...' outside a vararg function"),
+            Self::UpvalueBeforeDeclaration(name) => write!(f, "attempt to use upvalue '{}' before it is assigned", name),
+            Self::NonClosableValue(name) => write!(f, "variable '{}' got a non-closable value", name),
+            Self::BadArgument(position, function, message) =>
+                write!(f, "bad argument #{} to '{}' ({})", position, function, message),
+            Self::Custom(message) => write!(f, "{}", message),
+            Self::Runtime(value @ (Value::String(_) | Value::Number(_))) => write!(f, "{}", value.to_lua_string()),
+            Self::Runtime(value) => write!(f, "(error object is a {} value)", value.type_name()),
         }
     }
 }
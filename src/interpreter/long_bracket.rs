@@ -0,0 +1,84 @@
+// The offset and contents of each long string in a script, in order.
+pub type LongStrings = Vec<(usize, String)>;
+
+/// Blanks out the long comments in a script, such as `--[==[ ... ]==]`, and
+/// the contents of its long strings, so the parser doesn't have to pair up
+/// their brackets. The script keeps its length and its newlines, so offsets
+/// and lines into it are unchanged.
+///
+/// Gives the blanked script, with the offset and contents of each long
+/// string, or the offset and a description of an unfinished one.
+pub fn blank_long_brackets(source: &str) -> Result<(String, LongStrings), (usize, &'static str)> {
+    let bytes = source.as_bytes();
+    let mut blanked = bytes.to_vec();
+    let mut long_strings = Vec::new();
+
+    let mut offset = 0;
+    while offset < bytes.len() {
+        match bytes[offset] {
+            // NOTE: Strings have no escapes, so end at the next quote.
+            b'"' => match source[offset + 1..].find('"') {
+                Some(end) => offset += end + 2,
+                None => break,
+            },
+
+            b'-' if bytes.get(offset + 1) == Some(&b'-') => {
+                let Some(level) = opening_level(bytes, offset + 2) else {
+                    offset += source[offset..].find('\n').unwrap_or(source.len() - offset);
+                    continue;
+                };
+
+                let end = closing_end(source, offset + level + 4, level)
+                    .ok_or((offset, "unfinished long comment"))?;
+                blank(&mut blanked[offset..end]);
+                offset = end;
+            },
+
+            // NOTE: A newline straight after the opening bracket of a long
+            //       string isn't part of it.
+            b'[' => {
+                let Some(level) = opening_level(bytes, offset) else {
+                    offset += 1;
+                    continue;
+                };
+
+                let start = offset + level + 2;
+                let end = closing_end(source, start, level)
+                    .ok_or((offset, "unfinished long string"))?;
+                let contents = &source[start..end - level - 2];
+                let contents = contents.strip_prefix("\r\n").or_else(|| contents.strip_prefix('\n')).unwrap_or(contents);
+                long_strings.push((offset, contents.to_owned()));
+                blank(&mut blanked[start..end - level - 2]);
+                offset = end;
+            },
+
+            _ => offset += 1,
+        }
+    }
+
+    let blanked = String::from_utf8(blanked).expect("Only whole characters are blanked");
+    Ok((blanked, long_strings))
+}
+
+/// The level of the long bracket opening at `offset`, which is the number of
+/// `=` between its brackets, if there is one.
+fn opening_level(bytes: &[u8], offset: usize) -> Option<usize> {
+    if bytes.get(offset) != Some(&b'[') {
+        return None;
+    }
+
+    let level = bytes[offset + 1..].iter().take_while(|byte| **byte == b'=').count();
+    (bytes.get(offset + level + 1) == Some(&b'[')).then_some(level)
+}
+
+/// The offset just past the first closing bracket of `level` from `offset`.
+fn closing_end(source: &str, offset: usize, level: usize) -> Option<usize> {
+    let closing = format!("]{}]", "=".repeat(level));
+    source[offset..].find(&closing).map(|start| offset + start + closing.len())
+}
+
+fn blank(bytes: &mut [u8]) {
+    for byte in bytes.iter_mut().filter(|byte| !byte.is_ascii_whitespace()) {
+        *byte = b' ';
+    }
+}
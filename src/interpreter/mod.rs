@@ -1,43 +1,198 @@
-use crate::ast::{Statement, Expression, Term, Operation, Function, TableConstructionIndex};
+use crate::ast::{Statement, Attribute, Expression, Term, Operation, UnaryOperation, Function, TableConstructionIndex};
 use crate::lua_parser;
 use std::rc::Rc;
-use std::cell::RefCell;
-use value::{Scope, Index, Table, FunctionCapture};
+use std::cell::{Cell, RefCell};
+use std::cmp::Ordering;
+use std::iter;
+use std::ops::ControlFlow;
+use value::{Scope, Index, Table, FunctionCapture, NativeFunction, IntoNativeClosure};
 
 pub use value::Value;
-pub use error::LuaError;
+pub(crate) use value::parse_number;
+pub use error::{LuaError, ParseError};
 pub type Result<T> = std::result::Result<T, LuaError>;
 
 mod value;
 mod error;
+mod check;
+mod long_bracket;
+mod pattern;
+mod stdlib;
+
+// How many tables a metamethod lookup may pass through.
+const MAX_METAMETHOD_CHAIN: usize = 2000;
+
+thread_local! {
+    // NOTE: Building the parser takes a while, so every interpreter on a
+    //       thread shares one.
+    static PARSER: lua_parser::ProgramParser = lua_parser::ProgramParser::new();
+}
+
+enum AssignTarget {
+    Variable(String),
+    Table(Rc<RefCell<Table>>, Index),
+}
+
+/// The globals of an interpreter at some point, which can be restored later.
+///
+/// Only the globals table itself is copied, so tables held in globals are
+/// still shared with the interpreter. Restoring puts back which table each
+/// global refers to, but not the contents of those tables, unless they were
+/// replaced rather than modified.
+pub struct Snapshot {
+    globals: Table,
+}
 
 pub struct Interpreter {
-    global_scope: Scope,
-    parser: lua_parser::ProgramParser,
+    globals: Rc<RefCell<Table>>,
+    strict_globals: bool,
+    isolated_functions: bool,
+    step_count: u64,
+    max_nesting_depth: usize,
+
+    // The state of `math.random`, where 0 means it's not been seeded yet.
+    random_state: u64,
+
+    // The line each function on the call stack was called from, innermost
+    // last, or none for those called from native code.
+    call_lines: Vec<Option<usize>>,
+    chunk_name: String,
+}
+
+impl Default for Interpreter {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Interpreter {
     pub fn new() -> Self {
-        Interpreter {
-            global_scope: Scope::default(),
-            parser: lua_parser::ProgramParser::new(),
-        }
+        let globals = Rc::new(RefCell::new(Table::default()));
+        globals.borrow_mut().insert(Index::Name("_G".to_owned()), Value::Table(globals.clone()));
+
+        let mut interpreter = Interpreter {
+            globals,
+            strict_globals: false,
+            isolated_functions: false,
+            step_count: 0,
+            max_nesting_depth: 200,
+            random_state: 0,
+            call_lines: Vec::new(),
+            chunk_name: "chunk".to_owned(),
+        };
+
+        stdlib::load(&mut interpreter);
+        interpreter
     }
 
-    pub fn execute(&mut self, source: &str) -> Result<Value> {
-        let program = self.parser.parse(source).unwrap();
+    /// Sets the name positions in error messages give for scripts, which
+    /// is `chunk` by default.
+    pub fn set_chunk_name(&mut self, chunk_name: &str) {
+        self.chunk_name = chunk_name.to_owned();
+    }
+
+    /// When enabled, assigning to a name that's neither a local nor an
+    /// existing global is an error. New globals can still be created
+    /// through the `_G` table, or with a function declaration.
+    pub fn set_strict_globals(&mut self, strict_globals: bool) {
+        self.strict_globals = strict_globals;
+    }
+
+    /// When enabled, functions defined in scripts can't read globals, only
+    /// their own locals, the locals they capture and their arguments. The
+    /// main chunk still sees globals, so can pass them in explicitly.
+    pub fn set_isolated_functions(&mut self, isolated_functions: bool) {
+        self.isolated_functions = isolated_functions;
+    }
+
+    /// Sets how deeply brackets, operators and blocks may be nested in a
+    /// script, which is 200 by default. Each operation in a chain such as
+    /// `1 + 2 + 3` is a level. Scripts nested any deeper fail to parse,
+    /// rather than overflowing the stack while running.
+    pub fn set_max_nesting_depth(&mut self, max_nesting_depth: usize) {
+        self.max_nesting_depth = max_nesting_depth;
+    }
+
+    /// Takes a copy of the globals, so any changes a script makes to them can
+    /// be undone with `restore`.
+    pub fn snapshot(&self) -> Snapshot {
+        Snapshot { globals: self.globals.borrow().clone() }
+    }
 
+    /// Puts the globals back to how they were when the snapshot was taken.
+    pub fn restore(&mut self, snapshot: Snapshot) {
+        *self.globals.borrow_mut() = snapshot.globals;
+    }
+
+    /// The number of statements and expressions evaluated by the last call
+    /// to `execute`, including any that failed with an error.
+    pub fn last_step_count(&self) -> u64 {
+        self.step_count
+    }
+
+    pub fn execute(&mut self, source: &str) -> Result<Value> {
+        self.step_count = 0;
+
+        let depth = Cell::new(self.max_nesting_depth);
+        let line_starts = iter::once(0)
+            .chain(source.match_indices('\n').map(|(offset, _)| offset + 1))
+            .collect::<Vec<_>>();
+        let parse_error = |error| LuaError::ParseError(ParseError::new(source, error));
+        let (blanked, long_strings) = long_bracket::blank_long_brackets(source)
+            .map_err(|error| parse_error(lalrpop_util::ParseError::User { error }))?;
+        let program = PARSER.with(|parser| parser.parse(&depth, &line_starts, &long_strings, &blanked))
+            .map_err(parse_error)?;
+        check::check_program(&program)?;
+
+        // NOTE: The main chunk is variadic, though there are no arguments
+        //       passed to it yet.
         let mut scope = Scope::default();
-        Ok(self.execute_body(&mut scope, &program)?.unwrap_or(Value::Nil))
+        scope.varargs = Some(Vec::new());
+        let values = catch_panics(|| self.execute_body(&mut scope, &program))?.unwrap_or_default();
+        Ok(values.into_iter().next().unwrap_or(Value::Nil))
+    }
+
+    pub fn define(&mut self, name: &str, func: NativeFunction) {
+        self.set_global(name, Value::NativeFunction(func));
     }
 
-    pub fn define(&mut self, name: &str, func: fn(Vec<Value>) -> Value) {
-        self.global_scope.put(name.to_owned(), Value::NativeFunction(func));
+    /// Defines a global native function that can capture state, and gives
+    /// its result as a plain Rust value, such as `define_fn("pi", || PI)`.
+    pub fn define_fn<Arguments>(&mut self, name: &str, function: impl IntoNativeClosure<Arguments>) {
+        self.set_global(name, Value::NativeClosure(function.into_native_closure()));
     }
 
-    fn execute_body(&mut self, scope: &mut Scope, body: &Vec<Statement>) -> Result<Option<Value>> {
+    /// Converts a value to a string the way `tostring` does, which calls
+    /// the `__tostring` metamethod of the value if it has one.
+    pub fn tostring(&mut self, value: &Value) -> Result<String> {
+        let Some(metamethod) = lookup_metamethod(value, "__tostring") else {
+            return Ok(value.to_lua_string());
+        };
+
+        match self.call_metamethod(metamethod, vec![value.clone()])? {
+            Value::String(s) => Ok(s),
+            Value::Number(n) => Ok(value::format_number(n)),
+            _ => Err(LuaError::Custom("'__tostring' must return a string".to_owned())),
+        }
+    }
+
+    fn get_global(&self, name: &str) -> Value {
+        self.globals.borrow().get(&Index::Name(name.to_owned())).cloned().unwrap_or(Value::Nil)
+    }
+
+    fn set_global(&mut self, name: &str, value: Value) {
+        self.globals.borrow_mut().insert(Index::Name(name.to_owned()), value);
+    }
+
+    fn execute_body(&mut self, scope: &mut Scope, body: &Vec<Statement>) -> Result<Option<Vec<Value>>> {
+        let first_to_close = scope.to_close.len();
+        let result = self.execute_statements(scope, body);
+        self.close_variables(scope, first_to_close, result)
+    }
+
+    fn execute_statements(&mut self, scope: &mut Scope, body: &Vec<Statement>) -> Result<Option<Vec<Value>>> {
         for statement in body {
-            if let Some(value) = self.execute_statement(scope, &statement)? {
+            if let Some(value) = self.execute_statement(scope, statement)? {
                 return Ok(Some(value))
             }
         }
@@ -45,19 +200,62 @@ impl Interpreter {
         Ok(None)
     }
 
-    fn execute_statement(&mut self, scope: &mut Scope, statement: &Statement) -> Result<Option<Value>> {
+    /// Calls the `__close` metamethod of each `<close>` local declared from
+    /// `first` onwards, in the opposite order they were declared. Each is
+    /// given the error the block exited with, or nil. An error raised while
+    /// closing replaces the one the block exited with, and the rest are
+    /// still closed.
+    fn close_variables<T>(&mut self, scope: &mut Scope, first: usize, mut result: Result<T>) -> Result<T> {
+        while scope.to_close.len() > first {
+            let value = scope.to_close.pop().expect("more values than the first");
+            let error = match &result {
+                Ok(_) => Value::Nil,
+                Err(LuaError::Runtime(value)) => value.clone(),
+                Err(error) => Value::String(error.to_string()),
+            };
+
+            // NOTE: The metamethod is looked up again, so it may have been
+            //       changed or removed since the local was declared.
+            let Some(metamethod) = lookup_metamethod(&value, "__close") else {
+                result = Err(LuaError::InvalidCall(Value::Nil));
+                continue;
+            };
+
+            if let Err(error) = self.call(metamethod, vec![value, error]) {
+                result = Err(error);
+            }
+        }
+
+        result
+    }
+
+    /// Runs a block in its own scope, so any locals declared within it are
+    /// dropped at the end of the block.
+    fn execute_block(&mut self, scope: &Scope, body: &Vec<Statement>) -> Result<Option<Vec<Value>>> {
+        let mut block_scope = scope.clone();
+        self.execute_body(&mut block_scope, body)
+    }
+
+    fn execute_statement(&mut self, scope: &mut Scope, statement: &Statement) -> Result<Option<Vec<Value>>> {
+        self.step_count += 1;
         Ok(match statement {
-            Statement::Assignment(lhs, rhs) => { self.execute_assign(scope, lhs, rhs)?; None },
+            Statement::Assignment(targets, values) => { self.execute_assign(scope, targets, values)?; None },
             Statement::Expression(expression) => { self.execute_expression(scope, expression)?; None },
-            Statement::Return(value) => Some(self.execute_expression(scope, value)?),
-            Statement::Local(name, value) => { self.execute_local(scope, name, value)?; None },
-            Statement::Function(function) => { self.execute_function(scope, function); None },
+            Statement::Return(values) => Some(self.execute_expression_list(scope, values)?),
+            Statement::Local(names, values) => { self.execute_local(scope, names, values)?; None },
+            Statement::Function(function) => { self.execute_function(scope, function)?; None },
+            Statement::LocalFunction(function) => { self.execute_local_function(scope, function); None },
+            Statement::Do(body) => self.execute_block(scope, body)?,
+            Statement::Repeat(body, condition) => self.execute_repeat(scope, body, condition)?,
 
             Statement::If(condition, then, elseif, else_) =>
                 self.execute_if(scope, condition, then, elseif, else_)?,
 
             Statement::NumericFor(name, initial_value, limit, step, body) =>
                 self.execute_numeric_for(scope, name, initial_value, limit, step, body)?,
+
+            Statement::GenericFor(names, values, body) =>
+                self.execute_generic_for(scope, names, values, body)?,
         })
     }
 
@@ -67,7 +265,7 @@ impl Interpreter {
                            initial_value: &Box<Expression>,
                            limit: &Box<Expression>,
                            step: &Option<Box<Expression>>,
-                           body: &Vec<Statement>) -> Result<Option<Value>> {
+                           body: &Vec<Statement>) -> Result<Option<Vec<Value>>> {
         let evaluated_initial_value = self.execute_expression(scope, initial_value)?;
         let mut value = match evaluated_initial_value {
             Value::Number(initial_value) => initial_value,
@@ -89,9 +287,16 @@ impl Interpreter {
             None => 1.0,
         };
 
-        while value <= limit {
-            scope.put(name.to_owned(), Value::Number(value));
-            if let Some(value) = self.execute_body(scope, body)? {
+        if step == 0.0 {
+            return Err(LuaError::ForStepIsZero);
+        }
+
+        while (step > 0.0 && value <= limit) || (step < 0.0 && value >= limit) {
+            // NOTE: Each iteration has its own copy of the loop variable, so
+            //       closures created in the body capture its current value.
+            let mut iteration_scope = scope.clone();
+            iteration_scope.declare(name.to_owned(), Value::Number(value));
+            if let Some(value) = self.execute_body(&mut iteration_scope, body)? {
                 return Ok(Some(value));
             }
 
@@ -101,64 +306,166 @@ impl Interpreter {
         Ok(None)
     }
 
+    // NOTE: The condition is evaluated in the scope of the body, so it can
+    //       use any locals the body declared.
+    fn execute_repeat(&mut self,
+                      scope: &mut Scope,
+                      body: &Vec<Statement>,
+                      condition: &Box<Expression>) -> Result<Option<Vec<Value>>> {
+        loop {
+            // NOTE: Locals in the body are closed after the condition, as
+            //       it's part of the body's block.
+            let mut iteration_scope = scope.clone();
+            let first_to_close = iteration_scope.to_close.len();
+            let result = self.execute_statements(&mut iteration_scope, body)
+                .and_then(|value| match value {
+                    Some(value) => Ok(ControlFlow::Break(Some(value))),
+                    None if self.execute_expression(&mut iteration_scope, condition)?.is_truthy() =>
+                        Ok(ControlFlow::Break(None)),
+                    None => Ok(ControlFlow::Continue(())),
+                });
+
+            if let ControlFlow::Break(value) = self.close_variables(&mut iteration_scope, first_to_close, result)? {
+                return Ok(value);
+            }
+        }
+    }
+
+    /// Runs `for names in values do body end`, where the values give an
+    /// iterator function, a state and an initial control value. Each loop
+    /// calls the iterator with the state and control value, stopping once
+    /// its first result is nil, otherwise that becomes the new control value.
+    fn execute_generic_for(&mut self,
+                           scope: &mut Scope,
+                           names: &[String],
+                           values: &[Box<Expression>],
+                           body: &Vec<Statement>) -> Result<Option<Vec<Value>>> {
+        let mut evaluated_values = self.execute_expression_list(scope, values)?.into_iter();
+        let iterator = evaluated_values.next().unwrap_or(Value::Nil);
+        let state = evaluated_values.next().unwrap_or(Value::Nil);
+        let mut control = evaluated_values.next().unwrap_or(Value::Nil);
+
+        loop {
+            let results = self.call(iterator.clone(), vec![state.clone(), control.clone()])?;
+            control = results.first().cloned().unwrap_or(Value::Nil);
+            if control == Value::Nil {
+                return Ok(None);
+            }
+
+            let mut results = results.into_iter();
+            let mut iteration_scope = scope.clone();
+            for name in names {
+                iteration_scope.declare(name.to_owned(), results.next().unwrap_or(Value::Nil));
+            }
+
+            if let Some(values) = self.execute_body(&mut iteration_scope, body)? {
+                return Ok(Some(values));
+            }
+        }
+    }
+
     fn execute_if(&mut self,
                   scope: &mut Scope,
                   condition: &Box<Expression>,
                   then: &Vec<Statement>,
                   elseif: &Vec<(Box<Expression>, Vec<Statement>)>,
-                  else_: &Option<Vec<Statement>>) -> Result<Option<Value>> {
+                  else_: &Option<Vec<Statement>>) -> Result<Option<Vec<Value>>> {
         let evaluated_condition = self.execute_expression(scope, condition)?;
         if evaluated_condition.is_truthy() {
-            return self.execute_body(scope, then);
+            return self.execute_block(scope, then);
         }
 
         for (condition, then) in elseif {
             let evaluated_condition = self.execute_expression(scope, condition)?;
             if evaluated_condition.is_truthy() {
-                return self.execute_body(scope, then);
+                return self.execute_block(scope, then);
             }
         }
 
         match else_ {
-            Some(body) => self.execute_body(scope, body),
+            Some(body) => self.execute_block(scope, body),
             None => Ok(None),
         }
     }
 
-    fn execute_local(&mut self, scope: &mut Scope, name: &str, value: &Box<Expression>) -> Result<()> {
-        let evaluated_value = self.execute_expression(scope, value)?;
-        scope.put(name.to_owned(), evaluated_value);
+    fn execute_local(&mut self,
+                     scope: &mut Scope,
+                     names: &[(String, Option<Attribute>)],
+                     values: &[Box<Expression>]) -> Result<()> {
+        // NOTE: Like assignment, names without a value are set to nil and any
+        //       values left over are discarded.
+        let mut evaluated_values = self.execute_expression_list(scope, values)?.into_iter();
+        for (name, attribute) in names {
+            let evaluated_value = evaluated_values.next().unwrap_or(Value::Nil);
+
+            // NOTE: Only values with a `__close` metamethod can be closed,
+            //       other than `nil` and `false`, which are ignored.
+            if *attribute == Some(Attribute::Close) && evaluated_value.is_truthy() {
+                if lookup_metamethod(&evaluated_value, "__close").is_none() {
+                    return Err(LuaError::NonClosableValue(name.to_owned()));
+                }
+                scope.to_close.push(evaluated_value.clone());
+            }
+
+            scope.declare(name.to_owned(), evaluated_value);
+        }
+
         Ok(())
     }
 
-    fn execute_function(&mut self, scope: &mut Scope, function: &Function) {
+    fn execute_function(&mut self, scope: &mut Scope, function: &Function) -> Result<()> {
+        let function_value = Value::Function(Rc::from(FunctionCapture {
+            parameters: function.parameters.clone(),
+            body: function.body.clone(),
+            capture: scope.clone(),
+        }));
+
+        let Some((field, path)) = function.path.split_last() else {
+            self.set_global(&function.name, function_value);
+            return Ok(());
+        };
+
+        let mut table = self.get_variable(scope, &function.name);
+        for name in path {
+            table = match table {
+                Value::Table(table) => self.get_from_table(&table, &Index::Name(name.to_owned()))?,
+                _ => return Err(LuaError::InvalidIndex(table)),
+            };
+        }
+
+        match table {
+            Value::Table(table) => self.set_in_table(&table, Index::Name(field.to_owned()), function_value),
+            _ => Err(LuaError::InvalidIndex(table)),
+        }
+    }
+
+    // NOTE: Unlike `local f = function() ... end`, the local is declared before
+    //       the function is created, so the function captures itself and can
+    //       be called recursively.
+    fn execute_local_function(&mut self, scope: &mut Scope, function: &Function) {
+        scope.declare(function.name.to_owned(), Value::Nil);
         let function_value = Value::Function(Rc::from(FunctionCapture {
             parameters: function.parameters.clone(),
             body: function.body.clone(),
             capture: scope.clone(),
         }));
 
-        self.global_scope.put(function.name.clone(), function_value);
+        scope.put(function.name.to_owned(), function_value);
     }
 
     fn execute_expression(&mut self, scope: &mut Scope, expression: &Box<Expression>) -> Result<Value> {
+        self.step_count += 1;
         Ok(match expression.as_ref() {
             Expression::Term(term) => self.execute_term(scope, term)?,
-            Expression::Binary(lhs, operation, rhs) => {
-                let lhs = self.execute_expression(scope, lhs)?;
-                let rhs = self.execute_expression(scope, rhs)?;
+            Expression::Binary(lhs, operation, rhs) =>
+                self.execute_binary_operation(scope, lhs, operation, rhs)?,
+
+            Expression::Unary(operation, operand_expression) => {
+                let operand = self.execute_expression(scope, operand_expression)?;
                 match operation {
-                    Operation::Add => value::execute_arithmetic_operation(lhs, rhs, |a, b| a + b)?,
-                    Operation::Subtract => value::execute_arithmetic_operation(lhs, rhs, |a, b| a - b)?,
-                    Operation::Multiply => value::execute_arithmetic_operation(lhs, rhs, |a, b| a * b)?,
-                    Operation::Divide => value::execute_arithmetic_operation(lhs, rhs, |a, b| a / b)?,
-
-                    Operation::Equals => value::execute_logic_operation(lhs, rhs, |a, b| a == b),
-                    Operation::GraterThan => value::execute_logic_operation(lhs, rhs, |a, b| a > b),
-                    Operation::LessThan => value::execute_logic_operation(lhs, rhs, |a, b| a < b),
-                    Operation::GraterThanEquals => value::execute_logic_operation(lhs, rhs, |a, b| a >= b),
-                    Operation::LessThanEquals  => value::execute_logic_operation(lhs, rhs, |a, b| a <= b),
-                }
+                    UnaryOperation::Negate => self.execute_negate_operation(operand),
+                    UnaryOperation::Length => self.execute_length_operation(operand),
+                }.map_err(|error| self.explain_nil_operand(&[operand_expression], error))?
             },
 
             Expression::Function(parameters, body) => Value::Function(Rc::from(FunctionCapture {
@@ -167,32 +474,195 @@ impl Interpreter {
                 capture: scope.clone(),
             })),
 
-            Expression::Call(callee, arguments) => self.execute_call(scope, callee, arguments)?,
+            Expression::Call(callee, arguments, line) =>
+                self.execute_call(scope, callee, arguments, *line)?.into_iter().next().unwrap_or(Value::Nil),
+
+            Expression::MethodCall(receiver, name, arguments, line) =>
+                self.execute_method_call(scope, receiver, name, arguments, *line)?.into_iter().next().unwrap_or(Value::Nil),
+
+            Expression::Parenthesised(expression) => self.execute_expression(scope, expression)?,
             Expression::Dot(value, name) => self.execute_dot_operation(scope, value, name)?,
             Expression::Index(value, index) => self.execute_index_operation(scope, value, index)?,
         })
     }
 
-    fn execute_assign(&mut self, scope: &mut Scope, lhs: &Box<Expression>, rhs: &Box<Expression>) -> Result<()> {
-        let evaluated_value = self.execute_expression(scope, rhs)?;
+    /// Evaluates an expression that may give any number of values, which
+    /// is either a call or `...`. Any other expression gives exactly one.
+    fn execute_multiple_values(&mut self, scope: &mut Scope, expression: &Box<Expression>) -> Result<Vec<Value>> {
+        let values = match expression.as_ref() {
+            Expression::Call(callee, arguments, line) => self.execute_call(scope, callee, arguments, *line)?,
+            Expression::MethodCall(receiver, name, arguments, line) =>
+                self.execute_method_call(scope, receiver, name, arguments, *line)?,
+            Expression::Term(Term::Vararg) => scope.varargs.clone().expect("checked to be in a variadic function"),
+            _ => return Ok(vec![self.execute_expression(scope, expression)?]),
+        };
 
-        match lhs.as_ref() {
-            Expression::Term(Term::Variable(name)) => {
-                if scope.has(name) {
-                    scope.put(name.to_owned(), evaluated_value);
-                } else {
-                    self.global_scope.put(name.to_owned(), evaluated_value);
-                }
-            },
+        self.step_count += 1;
+        Ok(values)
+    }
+
+    /// Evaluates a comma separated list of expressions, where only the last
+    /// one may expand to multiple values.
+    fn execute_expression_list(&mut self, scope: &mut Scope, expressions: &[Box<Expression>]) -> Result<Vec<Value>> {
+        let mut values = Vec::with_capacity(expressions.len());
+        if let Some((last, rest)) = expressions.split_last() {
+            for expression in rest {
+                values.push(self.execute_expression(scope, expression)?);
+            }
+
+            values.extend(self.execute_multiple_values(scope, last)?);
+        }
+
+        Ok(values)
+    }
+
+    fn execute_binary_operation(&mut self,
+                                scope: &mut Scope,
+                                lhs: &Box<Expression>,
+                                operation: &Operation,
+                                rhs: &Box<Expression>) -> Result<Value> {
+        let operands = [lhs, rhs];
+        let lhs = self.execute_expression(scope, lhs)?;
+
+        // NOTE: `and` and `or` short-circuit, evaluating to one of their operands
+        //       rather than a boolean. This gives the `cond and a or b` idiom, with
+        //       the usual caveat that it evaluates to `b` whenever `a` is falsy.
+        match operation {
+            Operation::And if !lhs.is_truthy() => return Ok(lhs),
+            Operation::Or if lhs.is_truthy() => return Ok(lhs),
+            _ => {},
+        }
+
+        let rhs = self.execute_expression(scope, rhs)?;
+        self.apply_binary_operation(lhs, operation, rhs)
+            .map_err(|error| self.explain_nil_operand(&operands, error))
+    }
+
+    fn apply_binary_operation(&mut self, lhs: Value, operation: &Operation, rhs: Value) -> Result<Value> {
+        Ok(match operation {
+            Operation::Add => self.execute_arithmetic_operation(lhs, rhs, "__add", |a, b| a + b)?,
+            Operation::Subtract => self.execute_arithmetic_operation(lhs, rhs, "__sub", |a, b| a - b)?,
+            Operation::Multiply => self.execute_arithmetic_operation(lhs, rhs, "__mul", |a, b| a * b)?,
+            Operation::Divide => self.execute_arithmetic_operation(lhs, rhs, "__div", |a, b| a / b)?,
+
+            // NOTE: Lua's modulo rounds the quotient towards minus infinity, so unlike
+            //       Rust's `%` the result always takes the sign of the divisor.
+            Operation::Modulo => self.execute_arithmetic_operation(lhs, rhs, "__mod", |a, b| a - (a / b).floor() * b)?,
+            Operation::Power => self.execute_arithmetic_operation(lhs, rhs, "__pow", f64::powf)?,
+            Operation::Concat => self.execute_concat_operation(lhs, rhs)?,
+
+            Operation::Equals => Value::Boolean(self.execute_equality_operation(&lhs, &rhs)?),
+            Operation::NotEquals => Value::Boolean(!self.execute_equality_operation(&lhs, &rhs)?),
+            Operation::GraterThan => value::execute_comparison_operation(lhs, rhs, Ordering::is_gt)?,
+            Operation::LessThan => value::execute_comparison_operation(lhs, rhs, Ordering::is_lt)?,
+            Operation::GraterThanEquals => value::execute_comparison_operation(lhs, rhs, Ordering::is_ge)?,
+            Operation::LessThanEquals  => value::execute_comparison_operation(lhs, rhs, Ordering::is_le)?,
+
+            Operation::And | Operation::Or => rhs,
+        })
+    }
+
+    /// Runs an arithmetic operation, or if either operand isn't a number,
+    /// the metamethod for it from the metatable of the first operand that
+    /// has one.
+    fn execute_arithmetic_operation(&mut self,
+                                    lhs: Value,
+                                    rhs: Value,
+                                    event: &str,
+                                    number_operation: fn(f64, f64) -> f64) -> Result<Value> {
+        if value::coerce_to_number(&lhs).is_none() || value::coerce_to_number(&rhs).is_none() {
+            if let Some(metamethod) = lookup_metamethod(&lhs, event).or_else(|| lookup_metamethod(&rhs, event)) {
+                return self.call_metamethod(metamethod, vec![lhs, rhs]);
+            }
+        }
+
+        value::execute_arithmetic_operation(lhs, rhs, number_operation)
+    }
+
+    /// Concatenates two strings or numbers, or otherwise calls the
+    /// `__concat` metamethod of the first operand that has one.
+    fn execute_concat_operation(&mut self, lhs: Value, rhs: Value) -> Result<Value> {
+        let is_string_or_number = |value: &Value| matches!(value, Value::String(_) | Value::Number(_));
+        if !is_string_or_number(&lhs) || !is_string_or_number(&rhs) {
+            if let Some(metamethod) = lookup_metamethod(&lhs, "__concat").or_else(|| lookup_metamethod(&rhs, "__concat")) {
+                return self.call_metamethod(metamethod, vec![lhs, rhs]);
+            }
+        }
+
+        value::execute_concat_operation(lhs, rhs)
+    }
+
+    /// Negates a number or numeric string, or otherwise calls the operand's
+    /// `__unm` metamethod, which like Lua's is given the operand twice.
+    fn execute_negate_operation(&mut self, operand: Value) -> Result<Value> {
+        if value::coerce_to_number(&operand).is_none() {
+            if let Some(metamethod) = lookup_metamethod(&operand, "__unm") {
+                return self.call_metamethod(metamethod, vec![operand.clone(), operand]);
+            }
+        }
+
+        value::execute_negate_operation(operand)
+    }
+
+    /// Gives the length of a string, or of a table through its `__len`
+    /// metamethod if it has one, falling back to the border of its array.
+    fn execute_length_operation(&mut self, operand: Value) -> Result<Value> {
+        if let Some(metamethod) = lookup_metamethod(&operand, "__len") {
+            return self.call_metamethod(metamethod, vec![operand]);
+        }
+
+        value::execute_length_operation(operand)
+    }
+
+    // NOTE: `~=` is defined as the negation of this, so any changes to how
+    //       equality is decided apply to both operators. Two distinct tables
+    //       consult the `__eq` metamethod of the first that has one, and the
+    //       result is always converted to a boolean.
+    fn execute_equality_operation(&mut self, lhs: &Value, rhs: &Value) -> Result<bool> {
+        if value::is_equal(lhs, rhs) {
+            return Ok(true);
+        }
+
+        if let (Value::Table(_), Value::Table(_)) = (lhs, rhs) {
+            if let Some(metamethod) = lookup_metamethod(lhs, "__eq").or_else(|| lookup_metamethod(rhs, "__eq")) {
+                return Ok(self.call_metamethod(metamethod, vec![lhs.clone(), rhs.clone()])?.is_truthy());
+            }
+        }
+
+        Ok(false)
+    }
+
+    fn execute_assign(&mut self,
+                      scope: &mut Scope,
+                      targets: &[Box<Expression>],
+                      values: &[Box<Expression>]) -> Result<()> {
+        // NOTE: Targets and values are all evaluated before assigning any of
+        //       them, so `a, b = b, a` swaps the two and `i, t[i] = i + 1, 0`
+        //       assigns to the old `i` index. Targets without a value are set
+        //       to nil and any values left over are discarded.
+        let evaluated_targets = targets.iter()
+            .map(|target| self.evaluate_assign_target(scope, target))
+            .collect::<Result<Vec<_>>>()?;
+
+        let evaluated_values = self.execute_expression_list(scope, values)?;
+        let mut evaluated_values = evaluated_values.into_iter();
+        for target in evaluated_targets {
+            let value = evaluated_values.next().unwrap_or(Value::Nil);
+            self.assign_to(scope, target, value)?;
+        }
+
+        Ok(())
+    }
+
+    fn evaluate_assign_target(&mut self, scope: &mut Scope, target: &Box<Expression>) -> Result<AssignTarget> {
+        match target.as_ref() {
+            Expression::Term(Term::Variable(name, _)) => Ok(AssignTarget::Variable(name.to_owned())),
 
             Expression::Dot(table, name) => {
                 let table = self.execute_expression(scope, table)?;
                 match table {
-                    Value::Table(table) => {
-                        table.borrow_mut().insert(Index::Name(name.to_owned()), evaluated_value);
-                    },
-
-                    _ => return Err(LuaError::InvalidIndex(table)),
+                    Value::Table(table) => Ok(AssignTarget::Table(table, Index::Name(name.to_owned()))),
+                    _ => Err(LuaError::InvalidIndex(table)),
                 }
             },
 
@@ -201,14 +671,30 @@ impl Interpreter {
                 match table {
                     Value::Table(table) => {
                         let index = self.evaluate_index(scope, index)?;
-                        table.borrow_mut().insert(index, evaluated_value);
+                        Ok(AssignTarget::Table(table, index))
                     },
 
-                    _ => return Err(LuaError::InvalidIndex(table)),
+                    _ => Err(LuaError::InvalidIndex(table)),
+                }
+            },
+
+            _ => unreachable!("assignment targets are checked when parsing"),
+        }
+    }
+
+    fn assign_to(&mut self, scope: &mut Scope, target: AssignTarget, evaluated_value: Value) -> Result<()> {
+        match target {
+            AssignTarget::Variable(name) => {
+                if scope.has(&name) {
+                    scope.put(name, evaluated_value);
+                } else if self.strict_globals && self.get_global(&name) == Value::Nil {
+                    return Err(LuaError::Custom(format!("assignment to undeclared global '{}'", name)));
+                } else {
+                    self.set_global(&name, evaluated_value);
                 }
             },
 
-            _ => todo!("Throw error"),
+            AssignTarget::Table(table, index) => self.set_in_table(&table, index, evaluated_value)?,
         }
 
         Ok(())
@@ -219,125 +705,312 @@ impl Interpreter {
         let index = Index::Name(name.to_owned());
 
         match evaluated_value {
-            Value::Table(table) => {
-                Ok(table.borrow().get(&index).unwrap_or(&Value::Nil).clone())
-            },
-
-            _ => Err(LuaError::InvalidIndex(evaluated_value)),
+            Value::Table(table) => self.get_from_table(&table, &index),
+            _ => Err(self.explain_nil_operand(&[value], LuaError::InvalidIndex(evaluated_value))),
         }
     }
 
     fn execute_index_operation(&mut self, scope: &mut Scope, value: &Box<Expression>, index: &Box<Expression>) -> Result<Value> {
         let evaluated_value = self.execute_expression(scope, value)?;
-        let index = self.evaluate_index(scope, index)?;
+        let index = match self.evaluate_index(scope, index) {
+            Err(LuaError::InvalidTableIndex(index)) => match evaluated_value {
+                Value::Table(table) => return self.get_from_table_with(&table, None, || index.clone()),
+                _ => return Err(self.explain_nil_operand(&[value], LuaError::InvalidIndex(evaluated_value))),
+            },
+            index => index?,
+        };
 
         match evaluated_value {
-            Value::Table(table) => {
-                Ok(table.borrow().get(&index).unwrap_or(&Value::Nil).clone())
-            },
+            Value::Table(table) => self.get_from_table(&table, &index),
+            _ => Err(self.explain_nil_operand(&[value], LuaError::InvalidIndex(evaluated_value))),
+        }
+    }
 
-            _ => Err(LuaError::InvalidIndex(evaluated_value)),
+    /// Looks up an index in a table. If the table doesn't hold it, and has a
+    /// metatable with an `__index` field, that's either called with the
+    /// table and index, or is another table to look the index up in.
+    fn get_from_table(&mut self, table: &Rc<RefCell<Table>>, index: &Index) -> Result<Value> {
+        self.get_from_table_with(table, Some(index), || index.to_value())
+    }
+
+    /// Looks up a key in a table the same way as `get_from_table`, where a
+    /// key that can't be an index is never found in the table itself, but
+    /// is still given to an `__index` function.
+    fn get_from_table_with(&mut self,
+                           table: &Rc<RefCell<Table>>,
+                           index: Option<&Index>,
+                           key: impl Fn() -> Value) -> Result<Value> {
+        let mut table = table.clone();
+
+        // NOTE: Like the reference implementation, a chain of tables is
+        //       followed only so far, as it may loop back on itself.
+        for _ in 0..MAX_METAMETHOD_CHAIN {
+            if let Some(value) = index.and_then(|index| table.borrow().get(index).cloned()) {
+                return Ok(value);
+            }
+
+            match lookup_metamethod(&Value::Table(table.clone()), "__index") {
+                Some(Value::Table(fallback)) => table = fallback,
+                Some(function) => return self.call_metamethod(function, vec![Value::Table(table), key()]),
+                None => return Ok(Value::Nil),
+            }
         }
+
+        Err(LuaError::Custom("'__index' chain too long; possibly a loop".to_owned()))
     }
 
-    fn evaluate_index(&mut self, scope: &mut Scope, index: &Box<Expression>) -> Result<Index> {
-        let evaluated_index = self.execute_expression(scope, index)?;
-        match evaluated_index {
-            Value::Number(n) => {
-                if f64::trunc(n) == n {
-                    Ok(Index::Number(n as i32))
-                } else {
-                    Ok(Index::Name(n.to_string()))
-                }
-            },
+    /// Sets an index in a table. If the table doesn't already hold it, and
+    /// has a metatable with a `__newindex` field, that's either called with
+    /// the table, index and value, or is another table to set the index in.
+    fn set_in_table(&mut self, table: &Rc<RefCell<Table>>, index: Index, value: Value) -> Result<()> {
+        let mut table = table.clone();
+        for _ in 0..MAX_METAMETHOD_CHAIN {
+            if table.borrow().get(&index).is_some() {
+                table.borrow_mut().insert(index, value);
+                return Ok(());
+            }
+
+            match lookup_metamethod(&Value::Table(table.clone()), "__newindex") {
+                Some(Value::Table(fallback)) => table = fallback,
+                Some(function) => {
+                    self.call(function, vec![Value::Table(table), index.to_value(), value])?;
+                    return Ok(());
+                },
+                None => {
+                    table.borrow_mut().insert(index, value);
+                    return Ok(());
+                },
+            }
+        }
 
-            Value::String(s) => Ok(Index::Name(s)),
+        Err(LuaError::Custom("'__newindex' chain too long; possibly a loop".to_owned()))
+    }
 
-            // FIXME: We should be able to use anything as an index.
-            _ => todo!("Throw error"),
+    // NOTE: Nil and NaN can't be indexes, so are an error when setting one,
+    //       but reading one just gives nil, as nothing can be stored there.
+    fn evaluate_index(&mut self, scope: &mut Scope, index: &Box<Expression>) -> Result<Index> {
+        match self.execute_expression(scope, index)? {
+            index @ Value::Nil => Err(LuaError::InvalidTableIndex(index)),
+            Value::Number(n) if n.is_nan() => Err(LuaError::InvalidTableIndex(Value::Number(n))),
+            index => Ok(Index::from_value(index).expect("only nil and NaN aren't indexes")),
+        }
+    }
+
+    /// Gives the local a name refers to, or else the global, which isolated
+    /// functions always see as nil.
+    fn get_variable(&self, scope: &Scope, name: &str) -> Value {
+        match scope.get(name) {
+            Some(value) => value,
+            None if scope.isolated => Value::Nil,
+            None => self.get_global(name),
         }
     }
 
     fn execute_term(&mut self, scope: &mut Scope, term: &Term) -> Result<Value> {
         Ok(match term {
+            Term::Nil => Value::Nil,
             Term::Number(n) => Value::Number(*n),
             Term::String(s) => Value::String(s.to_owned()),
             Term::Boolean(b) => Value::Boolean(*b),
-            Term::Variable(identifier) => {
-                scope.get(identifier)
-                    .unwrap_or(self.global_scope.get(identifier)
-                    .unwrap_or(Value::Nil))
-            },
+            Term::Variable(identifier, _) => self.get_variable(scope, identifier),
+            Term::Vararg => scope.varargs.as_ref()
+                .expect("checked to be in a variadic function")
+                .first().cloned().unwrap_or(Value::Nil),
+
             Term::Table(items) => self.execute_construct_table(scope, items)?,
         })
     }
 
     fn execute_construct_table(&mut self,
                                scope: &mut Scope,
-                               items: &Vec<(Option<TableConstructionIndex>, Box<Expression>)>) -> Result<Value> {
+                               items: &[(Option<TableConstructionIndex>, Box<Expression>)]) -> Result<Value> {
         let mut table = Table::default();
-        let mut current_numeric_index = 1i32;
 
-        for (index, value) in items {
-            let value = self.execute_expression(scope, value)?;
-            let index = match index {
-                Some(TableConstructionIndex::Name(name)) => Index::Name(name.to_owned()),
-                Some(TableConstructionIndex::Value(index)) => self.evaluate_index(scope, index)?,
-                None => {
-                    let index = Index::Number(current_numeric_index);
-                    current_numeric_index += 1;
-                    index
+        // NOTE: Like the reference implementation, positional items are only
+        //       stored once all other items have been, so `{[1] = "a", "b"}`
+        //       and `{"b", [1] = "a"}` both give `"b"` at index 1. Items are
+        //       still evaluated from left to right.
+        let mut positional_values = Vec::new();
+
+        for (i, (index, value)) in items.iter().enumerate() {
+            match index {
+                Some(TableConstructionIndex::Name(name)) => {
+                    let value = self.execute_expression(scope, value)?;
+                    table.insert(Index::Name(name.to_owned()), value);
+                },
+
+                Some(TableConstructionIndex::Value(index)) => {
+                    let index = self.evaluate_index(scope, index)?;
+                    let value = self.execute_expression(scope, value)?;
+                    table.insert(index, value);
                 },
-            };
 
-            table.insert(index, value);
+                // NOTE: A positional item at the end of the constructor is
+                //       expanded to all of its values, so `{...}` packs varargs.
+                None if i == items.len() - 1 =>
+                    positional_values.extend(self.execute_multiple_values(scope, value)?),
+
+                None => positional_values.push(self.execute_expression(scope, value)?),
+            }
+        }
+
+        for (index, value) in (1i64..).zip(positional_values) {
+            table.insert(Index::Number(index), value);
         }
 
         Ok(Value::Table(Rc::new(RefCell::new(table))))
     }
 
-    fn execute_call<'a>(&mut self,
-                        scope: &mut Scope,
-                        callee: &Box<Expression>,
-                        arguments: &Vec<Box<Expression>>) -> Result<Value> {
+    fn execute_call(&mut self,
+                    scope: &mut Scope,
+                    callee: &Box<Expression>,
+                    arguments: &[Box<Expression>],
+                    line: usize) -> Result<Vec<Value>> {
         let evaluated_callee = self.execute_expression(scope, callee)?;
-        match evaluated_callee {
-            Value::NativeFunction(func) =>
-                self.execute_native_call(scope, arguments, func),
+        let evaluated_arguments = self.execute_expression_list(scope, arguments)?;
+        if evaluated_callee == Value::Nil {
+            return Err(self.explain_nil_operand(&[callee], LuaError::InvalidCall(Value::Nil)));
+        }
+
+        self.call_from(Some(line), evaluated_callee, evaluated_arguments)
+    }
+
+    /// Calls `receiver:name(arguments)`, which is `receiver.name(receiver, arguments)`
+    /// with the receiver only evaluated once.
+    fn execute_method_call(&mut self,
+                           scope: &mut Scope,
+                           receiver: &Box<Expression>,
+                           name: &str,
+                           arguments: &[Box<Expression>],
+                           line: usize) -> Result<Vec<Value>> {
+        let evaluated_receiver = self.execute_expression(scope, receiver)?;
+        let method = match &evaluated_receiver {
+            Value::Table(table) => self.get_from_table(table, &Index::Name(name.to_owned()))?,
+            _ => return Err(self.explain_nil_operand(&[receiver], LuaError::InvalidIndex(evaluated_receiver))),
+        };
+
+        let mut evaluated_arguments = vec![evaluated_receiver];
+        evaluated_arguments.extend(self.execute_expression_list(scope, arguments)?);
+        self.call_from(Some(line), method, evaluated_arguments)
+    }
+
+    /// Replaces an error from using nil with one saying the nil came from a
+    /// closure using a local before it was declared, if that's where one of
+    /// the operands got it from.
+    fn explain_nil_operand(&self, operands: &[&Box<Expression>], error: LuaError) -> LuaError {
+        let is_nil_error = matches!(&error,
+            LuaError::InvalidArithmetic(Value::Nil)
+            | LuaError::InvalidConcat(Value::Nil)
+            | LuaError::InvalidLength(Value::Nil)
+            | LuaError::InvalidCall(Value::Nil)
+            | LuaError::InvalidIndex(Value::Nil)
+            | LuaError::InvalidComparison(Value::Nil, _)
+            | LuaError::InvalidComparison(_, Value::Nil));
+        if !is_nil_error {
+            return error;
+        }
 
-            Value::Function(function_capture) =>
-                self.execute_function_call(scope, arguments, &function_capture),
+        let declared_after_use = operands.iter().find_map(|operand| match operand.as_ref() {
+            Expression::Term(Term::Variable(name, declared_after_use))
+                if declared_after_use.get() && self.get_global(name) == Value::Nil => Some(name),
+            _ => None,
+        });
 
-            _ => Err(LuaError::InvalidCall(evaluated_callee)),
+        match declared_after_use {
+            Some(name) => LuaError::UpvalueBeforeDeclaration(name.to_owned()),
+            None => error,
         }
     }
 
-    fn execute_native_call<'a>(&mut self,
-                               scope: &mut Scope,
-                               arguments: &Vec<Box<Expression>>,
-                               func: fn(Vec<Value>) -> Value) -> Result<Value> {
-        Ok(func(arguments
-            .iter()
-            .map(|argument| self.execute_expression(scope, argument))
-            .collect::<Result<Vec<_>>>()?))
-    }
-
-    fn execute_function_call<'a>(&mut self,
-                                 scope: &mut Scope,
-                                 arguments: &Vec<Box<Expression>>,
-                                 function_capture: &FunctionCapture) -> Result<Value> {
+    fn call(&mut self, function: Value, arguments: Vec<Value>) -> Result<Vec<Value>> {
+        self.call_from(None, function, arguments)
+    }
+
+    /// Calls a function from the given line of a script, or from native code
+    /// if there's no line.
+    fn call_from(&mut self, line: Option<usize>, function: Value, arguments: Vec<Value>) -> Result<Vec<Value>> {
+        self.call_lines.push(line);
+        let result = catch_panics(|| match function {
+            Value::NativeFunction(func) => func(self, arguments),
+            Value::NativeClosure(function) => function.call(arguments),
+            Value::Function(function_capture) => self.execute_function_call(arguments, &function_capture),
+            _ => Err(LuaError::InvalidCall(function)),
+        });
+
+        self.call_lines.pop();
+        result
+    }
+
+    /// Where the function at `level` of the call stack is, as `chunk:line:`,
+    /// where level 1 is the innermost function. This is the line it's
+    /// currently calling from, so for a native function asking, the line it
+    /// was called from. Functions called from native code have no position.
+    fn position(&self, level: usize) -> Option<String> {
+        let index = self.call_lines.len().checked_sub(level)?;
+        let line = (*self.call_lines.get(index)?)?;
+        Some(format!("{}:{}:", self.chunk_name, line))
+    }
+
+    // NOTE: Metamethods only ever give their first result.
+    fn call_metamethod(&mut self, metamethod: Value, arguments: Vec<Value>) -> Result<Value> {
+        Ok(self.call(metamethod, arguments)?.into_iter().next().unwrap_or(Value::Nil))
+    }
+
+    fn execute_function_call(&mut self,
+                             arguments: Vec<Value>,
+                             function_capture: &FunctionCapture) -> Result<Vec<Value>> {
         let parameters = &function_capture.parameters;
         let body = &function_capture.body;
-        if parameters.len() != arguments.len() {
-            // FIXME: This should be allowed
-            todo!("Throw error");
-        }
 
+        // NOTE: Parameters without an argument are nil, and any arguments
+        //       left over are collected by `...`, or dropped if the function
+        //       isn't variadic.
         let mut function_scope = function_capture.capture.clone();
-        for (argument, parameter) in arguments.iter().zip(parameters) {
-            function_scope.put(parameter.to_owned(), self.execute_expression(scope, argument)?);
+        function_scope.varargs = None;
+        function_scope.isolated = self.isolated_functions;
+        function_scope.to_close = Vec::new();
+
+        let mut arguments = arguments.into_iter();
+        for parameter in parameters {
+            if parameter == "..." {
+                function_scope.varargs = Some(arguments.by_ref().collect());
+            } else {
+                function_scope.declare(parameter.to_owned(), arguments.next().unwrap_or(Value::Nil));
+            }
         }
 
-        Ok(self.execute_body(&mut function_scope, body)?.unwrap_or(Value::Nil))
+        Ok(self.execute_body(&mut function_scope, body)?.unwrap_or_default())
     }
 }
+
+/// Finds the field `name` in the metatable of a value, if it has one.
+fn lookup_metamethod(value: &Value, name: &str) -> Option<Value> {
+    let Value::Table(table) = value else {
+        return None;
+    };
+
+    let metatable = table.borrow().metatable()?;
+    let metamethod = metatable.borrow().get(&Index::Name(name.to_owned())).cloned();
+    metamethod
+}
+
+/// Runs `f`, turning a panic into an internal error. The default panic hook
+/// still reports it on stderr, and anything `f` was part way through
+/// changing is left as it was at the point of the panic. Aborts, such as a
+/// stack overflow, can't be caught at all.
+#[cfg(feature = "catch-panics")]
+fn catch_panics<T>(f: impl FnOnce() -> Result<T>) -> Result<T> {
+    std::panic::catch_unwind(std::panic::AssertUnwindSafe(f))
+        .unwrap_or_else(|payload| {
+            let message = payload.downcast_ref::<&str>().copied()
+                .or_else(|| payload.downcast_ref::<String>().map(String::as_str))
+                .unwrap_or("unknown panic");
+
+            Err(LuaError::Custom(format!("internal error: {}", message)))
+        })
+}
+
+#[cfg(not(feature = "catch-panics"))]
+fn catch_panics<T>(f: impl FnOnce() -> Result<T>) -> Result<T> {
+    f()
+}
@@ -0,0 +1,421 @@
+// Lua's patterns, which are matched against the bytes of a string. Unlike
+// regular expressions there's no alternation, and quantifiers only apply to
+// a single character class.
+
+use super::{LuaError, Result, Value};
+
+// How deeply matching may recurse, which is about once for each quantifier
+// or capture in the pattern.
+const MAX_RECURSION: usize = 200;
+
+pub const SPECIAL_CHARACTERS: &str = "^$*+?.([%-";
+
+#[derive(Debug, Clone, Copy)]
+pub enum Capture {
+    Substring(usize, usize),
+    Position(usize),
+}
+
+impl Capture {
+    // NOTE: Patterns match bytes, so a capture may split a multi-byte
+    //       character, which is then replaced with U+FFFD.
+    pub fn to_value(self, subject: &[u8]) -> Value {
+        match self {
+            Capture::Substring(start, end) =>
+                Value::String(String::from_utf8_lossy(&subject[start..end]).into_owned()),
+            Capture::Position(position) => Value::Number((position + 1) as f64),
+        }
+    }
+}
+
+/// Where a pattern matched, as a range of bytes, and what it captured.
+#[derive(Debug)]
+pub struct Match {
+    pub start: usize,
+    pub end: usize,
+    pub captures: Vec<Capture>,
+}
+
+impl Match {
+    /// Gives a capture, where a pattern without any captures has the whole
+    /// match as its only one.
+    pub fn capture(&self, index: usize) -> Option<Capture> {
+        if self.captures.is_empty() && index == 0 {
+            Some(Capture::Substring(self.start, self.end))
+        } else {
+            self.captures.get(index).copied()
+        }
+    }
+
+    pub fn capture_values(&self, subject: &[u8]) -> Vec<Value> {
+        if self.captures.is_empty() {
+            vec![Capture::Substring(self.start, self.end).to_value(subject)]
+        } else {
+            self.captures.iter().map(|capture| capture.to_value(subject)).collect()
+        }
+    }
+}
+
+/// Splits a leading `^` off of a pattern, giving whether it was there.
+pub fn split_anchor(pattern: &str) -> (bool, &str) {
+    match pattern.strip_prefix('^') {
+        Some(pattern) => (true, pattern),
+        None => (false, pattern),
+    }
+}
+
+/// Finds the first match of the pattern starting at or after `init`.
+pub fn find(subject: &[u8], pattern: &str, init: usize) -> Result<Option<Match>> {
+    let (anchored, pattern) = split_anchor(pattern);
+    for start in init..=subject.len() {
+        if let Some(found) = match_at(subject, pattern, start)? {
+            return Ok(Some(found));
+        }
+
+        if anchored {
+            break;
+        }
+    }
+
+    Ok(None)
+}
+
+/// Matches the pattern starting exactly at `start`. A leading `^` isn't
+/// treated as an anchor here.
+pub fn match_at(subject: &[u8], pattern: &str, start: usize) -> Result<Option<Match>> {
+    let mut matcher = Matcher {
+        subject,
+        pattern: pattern.as_bytes(),
+        captures: Vec::new(),
+        depth: 0,
+    };
+
+    let end = match matcher.do_match(start, 0)? {
+        Some(end) => end,
+        None => return Ok(None),
+    };
+
+    let captures = matcher.captures.iter()
+        .map(|(capture_start, length)| match length {
+            CaptureLength::Closed(length) => Ok(Capture::Substring(*capture_start, capture_start + length)),
+            CaptureLength::Position => Ok(Capture::Position(*capture_start)),
+            CaptureLength::Unclosed => Err(pattern_error("unfinished capture")),
+        })
+        .collect::<Result<_>>()?;
+
+    Ok(Some(Match { start, end, captures }))
+}
+
+fn pattern_error(message: &str) -> LuaError {
+    LuaError::Custom(message.to_owned())
+}
+
+#[derive(Debug, Clone, Copy)]
+enum CaptureLength {
+    Unclosed,
+    Position,
+    Closed(usize),
+}
+
+struct Matcher<'a> {
+    subject: &'a [u8],
+    pattern: &'a [u8],
+
+    // The start of each capture, in the order they were opened.
+    captures: Vec<(usize, CaptureLength)>,
+    depth: usize,
+}
+
+impl Matcher<'_> {
+    /// Matches the pattern from `p` against the subject from `s`, giving the
+    /// end of the match.
+    fn do_match(&mut self, s: usize, p: usize) -> Result<Option<usize>> {
+        if self.depth == MAX_RECURSION {
+            return Err(pattern_error("pattern too complex"));
+        }
+
+        self.depth += 1;
+        let result = self.match_here(s, p);
+        self.depth -= 1;
+        result
+    }
+
+    fn match_here(&mut self, mut s: usize, mut p: usize) -> Result<Option<usize>> {
+        let pattern = self.pattern;
+        loop {
+            if p == pattern.len() {
+                return Ok(Some(s));
+            }
+
+            match (pattern[p], pattern.get(p + 1)) {
+                (b'(', Some(b')')) => return self.start_capture(s, p + 2, CaptureLength::Position),
+                (b'(', _) => return self.start_capture(s, p + 1, CaptureLength::Unclosed),
+                (b')', _) => return self.end_capture(s, p + 1),
+
+                // NOTE: A `$` anywhere other than the end is an ordinary character.
+                (b'$', None) => return Ok((s == self.subject.len()).then_some(s)),
+
+                (b'%', Some(b'b')) => match self.match_balance(s, p + 2)? {
+                    Some(end) => {
+                        s = end;
+                        p += 4;
+                        continue;
+                    },
+                    None => return Ok(None),
+                },
+
+                (b'%', Some(b'f')) => {
+                    p += 2;
+                    if pattern.get(p) != Some(&b'[') {
+                        return Err(pattern_error("missing '[' after '%f' in pattern"));
+                    }
+
+                    let end = self.class_end(p)?;
+                    let previous = if s == 0 { 0 } else { self.subject[s - 1] };
+                    let current = self.subject.get(s).copied().unwrap_or(0);
+                    if self.match_set(previous, p, end - 1) || !self.match_set(current, p, end - 1) {
+                        return Ok(None);
+                    }
+
+                    p = end;
+                    continue;
+                },
+
+                (b'%', Some(digit)) if digit.is_ascii_digit() => match self.match_capture(s, *digit)? {
+                    Some(end) => {
+                        s = end;
+                        p += 2;
+                        continue;
+                    },
+                    None => return Ok(None),
+                },
+
+                _ => {},
+            }
+
+            let end = self.class_end(p)?;
+            let matches = self.single_match(s, p, end);
+            match pattern.get(end) {
+                Some(b'?') => {
+                    if matches {
+                        if let Some(found) = self.do_match(s + 1, end + 1)? {
+                            return Ok(Some(found));
+                        }
+                    }
+                    p = end + 1;
+                },
+
+                Some(b'+') if matches => return self.max_expand(s + 1, p, end),
+                Some(b'+') => return Ok(None),
+                Some(b'*') => return self.max_expand(s, p, end),
+                Some(b'-') => return self.min_expand(s, p, end),
+
+                _ if matches => {
+                    s += 1;
+                    p = end;
+                },
+                _ => return Ok(None),
+            }
+        }
+    }
+
+    /// Matches as many repeats of the single character class as possible,
+    /// backing off until the rest of the pattern matches.
+    fn max_expand(&mut self, s: usize, p: usize, end: usize) -> Result<Option<usize>> {
+        let mut count = 0;
+        while self.single_match(s + count, p, end) {
+            count += 1;
+        }
+
+        loop {
+            if let Some(found) = self.do_match(s + count, end + 1)? {
+                return Ok(Some(found));
+            }
+
+            if count == 0 {
+                return Ok(None);
+            }
+            count -= 1;
+        }
+    }
+
+    /// Matches as few repeats of the single character class as possible.
+    fn min_expand(&mut self, mut s: usize, p: usize, end: usize) -> Result<Option<usize>> {
+        loop {
+            if let Some(found) = self.do_match(s, end + 1)? {
+                return Ok(Some(found));
+            }
+
+            if !self.single_match(s, p, end) {
+                return Ok(None);
+            }
+            s += 1;
+        }
+    }
+
+    fn start_capture(&mut self, s: usize, p: usize, length: CaptureLength) -> Result<Option<usize>> {
+        self.captures.push((s, length));
+        let result = self.do_match(s, p)?;
+        if result.is_none() {
+            self.captures.pop();
+        }
+
+        Ok(result)
+    }
+
+    fn end_capture(&mut self, s: usize, p: usize) -> Result<Option<usize>> {
+        let capture = self.captures.iter()
+            .rposition(|(_, length)| matches!(length, CaptureLength::Unclosed))
+            .ok_or_else(|| pattern_error("invalid pattern capture"))?;
+
+        self.captures[capture].1 = CaptureLength::Closed(s - self.captures[capture].0);
+        let result = self.do_match(s, p)?;
+        if result.is_none() {
+            self.captures[capture].1 = CaptureLength::Unclosed;
+        }
+
+        Ok(result)
+    }
+
+    /// Matches the same text as an earlier, closed, capture.
+    fn match_capture(&self, s: usize, digit: u8) -> Result<Option<usize>> {
+        let index = (digit - b'0') as usize;
+        let (start, length) = match index.checked_sub(1).and_then(|index| self.captures.get(index)) {
+            Some((start, CaptureLength::Closed(length))) => (*start, *length),
+            _ => return Err(LuaError::Custom(format!("invalid capture index %{}", index))),
+        };
+
+        let captured = &self.subject[start..start + length];
+        Ok(self.subject[s..].starts_with(captured).then_some(s + length))
+    }
+
+    /// Matches `%bxy`, which is a balanced run of text between `x` and `y`.
+    fn match_balance(&self, s: usize, p: usize) -> Result<Option<usize>> {
+        let (open, close) = match self.pattern.get(p..p + 2) {
+            Some([open, close]) => (*open, *close),
+            _ => return Err(pattern_error("malformed pattern (missing arguments to '%b')")),
+        };
+
+        if self.subject.get(s) != Some(&open) {
+            return Ok(None);
+        }
+
+        let mut depth = 0;
+        for (offset, c) in self.subject[s + 1..].iter().enumerate() {
+            if *c == close {
+                if depth == 0 {
+                    return Ok(Some(s + offset + 2));
+                }
+                depth -= 1;
+            } else if *c == open {
+                depth += 1;
+            }
+        }
+
+        Ok(None)
+    }
+
+    /// Gives the position just after the single character class at `p`.
+    fn class_end(&self, mut p: usize) -> Result<usize> {
+        let pattern = self.pattern;
+        let c = pattern[p];
+        p += 1;
+
+        match c {
+            b'%' if p == pattern.len() => Err(pattern_error("malformed pattern (ends with '%')")),
+            b'%' => Ok(p + 1),
+
+            b'[' => {
+                if pattern.get(p) == Some(&b'^') {
+                    p += 1;
+                }
+
+                // NOTE: A `]` straight after the opening bracket is part of the set.
+                loop {
+                    if p >= pattern.len() {
+                        return Err(pattern_error("malformed pattern (missing ']')"));
+                    }
+
+                    let c = pattern[p];
+                    p += 1;
+                    if c == b'%' && p < pattern.len() {
+                        p += 1;
+                    }
+
+                    if pattern.get(p) == Some(&b']') {
+                        return Ok(p + 1);
+                    }
+                }
+            },
+
+            _ => Ok(p),
+        }
+    }
+
+    /// Whether the subject at `s` matches the single character class from
+    /// `p` up to `end`.
+    fn single_match(&self, s: usize, p: usize, end: usize) -> bool {
+        let Some(&c) = self.subject.get(s) else {
+            return false;
+        };
+
+        match self.pattern[p] {
+            b'.' => true,
+            b'%' => match_class(c, self.pattern[p + 1]),
+            b'[' => self.match_set(c, p, end - 1),
+            literal => literal == c,
+        }
+    }
+
+    /// Whether `c` is in the set from the `[` at `p` to the `]` at `end`.
+    fn match_set(&self, c: u8, mut p: usize, end: usize) -> bool {
+        let pattern = self.pattern;
+        p += 1;
+
+        let negated = pattern[p] == b'^';
+        if negated {
+            p += 1;
+        }
+
+        while p < end {
+            if pattern[p] == b'%' && p + 1 < end {
+                if match_class(c, pattern[p + 1]) {
+                    return !negated;
+                }
+                p += 2;
+            } else if pattern[p + 1] == b'-' && p + 2 < end {
+                if (pattern[p]..=pattern[p + 2]).contains(&c) {
+                    return !negated;
+                }
+                p += 3;
+            } else {
+                if pattern[p] == c {
+                    return !negated;
+                }
+                p += 1;
+            }
+        }
+
+        negated
+    }
+}
+
+/// Whether `c` is in the class `%class`, where an upper case class is the
+/// complement of the lower case one. Any other character matches itself.
+fn match_class(c: u8, class: u8) -> bool {
+    let matches = match class.to_ascii_lowercase() {
+        b'a' => c.is_ascii_alphabetic(),
+        b'c' => c.is_ascii_control(),
+        b'd' => c.is_ascii_digit(),
+        b'g' => c.is_ascii_graphic(),
+        b'l' => c.is_ascii_lowercase(),
+        b'p' => c.is_ascii_punctuation(),
+        b's' => matches!(c, b' ' | b'\t'..=b'\r'),
+        b'u' => c.is_ascii_uppercase(),
+        b'w' => c.is_ascii_alphanumeric(),
+        b'x' => c.is_ascii_hexdigit(),
+        _ => return class == c,
+    };
+
+    if class.is_ascii_uppercase() { !matches } else { matches }
+}
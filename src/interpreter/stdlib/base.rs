@@ -0,0 +1,272 @@
+use std::iter;
+
+use super::{bad_argument_type, expect_integer, expect_number, expect_table};
+use crate::interpreter::value::{self, Index};
+use crate::interpreter::{lookup_metamethod, Interpreter, Value, LuaError, Result};
+
+pub fn load(interpreter: &mut Interpreter) {
+    interpreter.define("assert", assert);
+    interpreter.define("error", error);
+    interpreter.define("pcall", pcall);
+    interpreter.define("xpcall", xpcall);
+    interpreter.define("select", select);
+    interpreter.define("ipairs", ipairs);
+    interpreter.define("next", next);
+    interpreter.define("pairs", pairs);
+    interpreter.define("getmetatable", getmetatable);
+    interpreter.define("setmetatable", setmetatable);
+    interpreter.define("rawset", rawset);
+    interpreter.define("type", type_);
+    interpreter.define("tostring", tostring);
+    interpreter.define("tonumber", tonumber);
+}
+
+/// Gives back all of its arguments if the first is truthy, otherwise raises
+/// an error with the second as its message.
+fn assert(_: &mut Interpreter, arguments: Vec<Value>) -> Result<Vec<Value>> {
+    match arguments.first() {
+        Some(value) if value.is_truthy() => Ok(arguments),
+        Some(_) => Err(match arguments.into_iter().nth(1) {
+            Some(message) => LuaError::Runtime(message),
+            None => LuaError::Custom("assertion failed!".to_owned()),
+        }),
+        None => Err(LuaError::BadArgument(1, "assert", "value expected".to_owned())),
+    }
+}
+
+/// Raises an error with the first argument, which may be any value, as its
+/// message. A string message has the position of the function at the given
+/// level added before it, where 1, the default, is the function calling
+/// `error`, 2 is the function that called that, and 0 adds no position.
+fn error(interpreter: &mut Interpreter, arguments: Vec<Value>) -> Result<Vec<Value>> {
+    let level = match arguments.get(1) {
+        None | Some(Value::Nil) => 1,
+        Some(_) => expect_integer(&arguments, 2, "error")?,
+    };
+
+    let message = arguments.into_iter().next().unwrap_or(Value::Nil);
+    let position = usize::try_from(level).ok()
+        .filter(|level| *level > 0)
+        .and_then(|level| interpreter.position(level));
+
+    Err(LuaError::Runtime(match (message, position) {
+        (Value::String(message), Some(position)) => Value::String(format!("{} {}", position, message)),
+        (message, _) => message,
+    }))
+}
+
+/// Calls the first argument with the rest, giving `true` followed by its
+/// results, or `false` and the error message if it raised an error. Errors
+/// raised with `error` give back the value they were raised with.
+fn pcall(interpreter: &mut Interpreter, arguments: Vec<Value>) -> Result<Vec<Value>> {
+    let mut arguments = arguments.into_iter();
+    let function = arguments.next()
+        .ok_or_else(|| LuaError::BadArgument(1, "pcall", "value expected".to_owned()))?;
+
+    Ok(match interpreter.call(function, arguments.collect()) {
+        Ok(values) => iter::once(Value::Boolean(true)).chain(values).collect(),
+        Err(LuaError::Runtime(value)) => vec![Value::Boolean(false), value],
+        Err(error) => vec![Value::Boolean(false), Value::String(error.to_string())],
+    })
+}
+
+/// Like `pcall`, but an error is first given to the handler, which is the
+/// second argument, and its result is given instead of the error.
+fn xpcall(interpreter: &mut Interpreter, arguments: Vec<Value>) -> Result<Vec<Value>> {
+    let mut arguments = arguments.into_iter();
+    let function = arguments.next()
+        .ok_or_else(|| LuaError::BadArgument(1, "xpcall", "value expected".to_owned()))?;
+    let handler = arguments.next()
+        .ok_or_else(|| LuaError::BadArgument(2, "xpcall", "value expected".to_owned()))?;
+
+    let error = match interpreter.call(function, arguments.collect()) {
+        Ok(values) => return Ok(iter::once(Value::Boolean(true)).chain(values).collect()),
+        Err(LuaError::Runtime(value)) => value,
+        Err(error) => Value::String(error.to_string()),
+    };
+
+    // NOTE: An error in the handler isn't given to the handler again, as it
+    //       would most likely fail the same way.
+    Ok(match interpreter.call(handler, vec![error]) {
+        Ok(values) => vec![Value::Boolean(false), values.into_iter().next().unwrap_or(Value::Nil)],
+        Err(_) => vec![Value::Boolean(false), Value::String("error in error handling".to_owned())],
+    })
+}
+
+/// Gives an iterator over the sequence in a table, for use in a generic
+/// `for`. It stops at the first nil, even if there are more numeric keys.
+fn ipairs(_: &mut Interpreter, arguments: Vec<Value>) -> Result<Vec<Value>> {
+    expect_table(&arguments, 1, "ipairs")?;
+    Ok(vec![Value::NativeFunction(next_ipairs), arguments[0].clone(), Value::Number(0.0)])
+}
+
+// NOTE: As in Lua 5.4, values are looked up through `__index`.
+fn next_ipairs(interpreter: &mut Interpreter, arguments: Vec<Value>) -> Result<Vec<Value>> {
+    let table = expect_table(&arguments, 1, "ipairs")?;
+    let index = expect_integer(&arguments, 2, "ipairs")? + 1;
+    let value = interpreter.get_from_table(&table, &Index::Number(index))?;
+
+    Ok(match value {
+        Value::Nil => vec![Value::Nil],
+        value => vec![Value::Number(index as f64), value],
+    })
+}
+
+/// `next(t, key)` gives the key and value after `key` in `t`, or the first
+/// pair if `key` is nil, or nil once there are no more.
+fn next(_: &mut Interpreter, arguments: Vec<Value>) -> Result<Vec<Value>> {
+    let table = expect_table(&arguments, 1, "next")?;
+    let after = match arguments.get(1) {
+        None | Some(Value::Nil) => None,
+        Some(key) => Some(Index::from_value(key.clone())
+            .ok_or_else(|| LuaError::Custom("invalid key to 'next'".to_owned()))?),
+    };
+
+    let next = table.borrow().next(after.as_ref())?;
+    Ok(match next {
+        Some((key, value)) => vec![key.to_value(), value],
+        None => vec![Value::Nil],
+    })
+}
+
+/// Gives an iterator over every key and value in a table, for use in a
+/// generic `for`. A `__pairs` metamethod is called instead if there is one.
+fn pairs(interpreter: &mut Interpreter, arguments: Vec<Value>) -> Result<Vec<Value>> {
+    let table = arguments.first().cloned()
+        .ok_or_else(|| bad_argument_type(1, "pairs", "table", None))?;
+
+    if let Some(metamethod) = lookup_metamethod(&table, "__pairs") {
+        let mut results = interpreter.call(metamethod, vec![table])?;
+        results.resize(3, Value::Nil);
+        return Ok(results);
+    }
+
+    expect_table(&arguments, 1, "pairs")?;
+    Ok(vec![Value::NativeFunction(next), table, Value::Nil])
+}
+
+/// `select('#', ...)` gives the number of arguments after the first, and
+/// `select(n, ...)` gives all of them from the `n`th onwards. A negative `n`
+/// counts back from the last argument.
+fn select(_: &mut Interpreter, arguments: Vec<Value>) -> Result<Vec<Value>> {
+    let count = arguments.len().saturating_sub(1) as i64;
+    if let Some(Value::String(s)) = arguments.first() {
+        if s == "#" {
+            return Ok(vec![Value::Number(count as f64)]);
+        }
+    }
+
+    let n = expect_number(&arguments, 1, "select")? as i64;
+    let start = if n < 0 { count + n } else { n - 1 };
+    if n == 0 || start < 0 {
+        return Err(LuaError::BadArgument(1, "select", "index out of range".to_owned()));
+    }
+
+    Ok(arguments.into_iter().skip(start as usize + 1).collect())
+}
+
+// NOTE: Unlike the reference implementation, which ignores any extra
+//       arguments, `type` takes exactly one.
+fn type_(_: &mut Interpreter, arguments: Vec<Value>) -> Result<Vec<Value>> {
+    let value = arguments.first()
+        .ok_or_else(|| LuaError::BadArgument(1, "type", "value expected".to_owned()))?;
+    if arguments.len() > 1 {
+        return Err(LuaError::BadArgument(2, "type", "wrong number of arguments".to_owned()));
+    }
+
+    Ok(vec![Value::String(value.type_name().to_owned())])
+}
+
+fn tostring(interpreter: &mut Interpreter, arguments: Vec<Value>) -> Result<Vec<Value>> {
+    let value = arguments.first()
+        .ok_or_else(|| LuaError::BadArgument(1, "tostring", "value expected".to_owned()))?;
+
+    Ok(vec![Value::String(interpreter.tostring(value)?)])
+}
+
+/// Converts a string to a number, giving nil if it isn't one. With a base,
+/// the string must be an integer written in that base.
+fn tonumber(_: &mut Interpreter, arguments: Vec<Value>) -> Result<Vec<Value>> {
+    if arguments.get(1).is_none_or(|base| *base == Value::Nil) {
+        let value = arguments.first()
+            .ok_or_else(|| LuaError::BadArgument(1, "tonumber", "value expected".to_owned()))?;
+
+        return Ok(vec![value::coerce_to_number(value).map_or(Value::Nil, Value::Number)]);
+    }
+
+    let base = expect_integer(&arguments, 2, "tonumber")?;
+    if !(2..=36).contains(&base) {
+        return Err(LuaError::BadArgument(2, "tonumber", "base out of range".to_owned()));
+    }
+
+    let s = match arguments.first() {
+        Some(Value::String(s)) => s,
+        got => return Err(bad_argument_type(1, "tonumber", "string", got)),
+    };
+
+    let s = s.trim();
+    let (negative, digits) = match s.strip_prefix('-') {
+        Some(digits) => (true, digits),
+        None => (false, s),
+    };
+
+    if digits.is_empty() {
+        return Ok(vec![Value::Nil]);
+    }
+
+    let mut n = 0.0;
+    for digit in digits.chars() {
+        match digit.to_digit(base as u32) {
+            Some(digit) => n = n * base as f64 + digit as f64,
+            None => return Ok(vec![Value::Nil]),
+        }
+    }
+
+    Ok(vec![Value::Number(if negative { -n } else { n })])
+}
+
+fn protected_metatable(metatable: &Value) -> Option<Value> {
+    match metatable {
+        Value::Table(metatable) => metatable.borrow().get(&Index::Name("__metatable".to_owned())).cloned(),
+        _ => None,
+    }
+}
+
+/// Gives the metatable of a table, or its `__metatable` field if it has one.
+fn getmetatable(_: &mut Interpreter, arguments: Vec<Value>) -> Result<Vec<Value>> {
+    let metatable = match arguments.first() {
+        Some(Value::Table(table)) => table.borrow().metatable().map_or(Value::Nil, Value::Table),
+        Some(_) => Value::Nil,
+        None => return Err(LuaError::BadArgument(1, "getmetatable", "value expected".to_owned())),
+    };
+
+    Ok(vec![protected_metatable(&metatable).unwrap_or(metatable)])
+}
+
+/// Sets, or with nil removes, the metatable of a table, giving the table.
+fn setmetatable(_: &mut Interpreter, arguments: Vec<Value>) -> Result<Vec<Value>> {
+    let table = expect_table(&arguments, 1, "setmetatable")?;
+    let metatable = match arguments.get(1) {
+        Some(Value::Table(metatable)) => Some(metatable.clone()),
+        Some(Value::Nil) => None,
+        got => return Err(bad_argument_type(2, "setmetatable", "nil or table", got)),
+    };
+
+    let current = table.borrow().metatable().map_or(Value::Nil, Value::Table);
+    if protected_metatable(&current).is_some() {
+        return Err(LuaError::Custom("cannot change a protected metatable".to_owned()));
+    }
+
+    table.borrow_mut().set_metatable(metatable);
+    Ok(vec![Value::Table(table)])
+}
+
+/// Sets an index in a table, without calling any `__newindex` metamethod.
+fn rawset(_: &mut Interpreter, arguments: Vec<Value>) -> Result<Vec<Value>> {
+    let table = expect_table(&arguments, 1, "rawset")?;
+    let key = arguments.get(1).cloned().unwrap_or(Value::Nil);
+    let index = Index::from_value(key.clone()).ok_or(LuaError::InvalidTableIndex(key))?;
+
+    table.borrow_mut().insert(index, arguments.get(2).cloned().unwrap_or(Value::Nil));
+    Ok(vec![Value::Table(table)])
+}
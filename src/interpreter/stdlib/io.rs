@@ -0,0 +1,176 @@
+use std::cell::RefCell;
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, Read, Write};
+use std::rc::Rc;
+
+use super::{library, expect_string, expect_table};
+use super::value::{self, Index, NativeClosure, Table};
+use crate::interpreter::{Interpreter, Value, LuaError, Result};
+
+pub fn load(interpreter: &mut Interpreter) {
+    interpreter.set_global("io", library(&[
+        ("open", open),
+    ]));
+}
+
+// NOTE: There are no userdata values, so a file handle is a table with its
+//       methods in the `__index` of its metatable. Each handle has its own
+//       methods, which share the open file, so the file is closed once the
+//       handle and anything holding its methods are dropped. Reads are
+//       buffered and writes aren't, so a file opened for both should only
+//       be written to before it's first read from.
+type OpenFile = Rc<RefCell<Option<BufReader<File>>>>;
+
+/// Opens a file with one of C's `fopen` modes, giving a handle for it, or
+/// nil and a message if it couldn't be opened.
+fn open(_: &mut Interpreter, arguments: Vec<Value>) -> Result<Vec<Value>> {
+    let path = expect_string(&arguments, 1, "open")?;
+    let mode = match arguments.get(1) {
+        None | Some(Value::Nil) => "r".to_owned(),
+        Some(_) => expect_string(&arguments, 2, "open")?,
+    };
+
+    let mut options = OpenOptions::new();
+    match mode.trim_end_matches('b') {
+        "r" => options.read(true),
+        "w" => options.write(true).create(true).truncate(true),
+        "a" => options.append(true).create(true),
+        "r+" => options.read(true).write(true),
+        "w+" => options.read(true).write(true).create(true).truncate(true),
+        "a+" => options.read(true).append(true).create(true),
+        _ => return Err(LuaError::BadArgument(2, "open", "invalid mode".to_owned())),
+    };
+
+    let file = match options.open(&path) {
+        Ok(file) => file,
+        Err(error) => return Ok(vec![Value::Nil, Value::String(format!("{}: {}", path, error))]),
+    };
+
+    let file: OpenFile = Rc::new(RefCell::new(Some(BufReader::new(file))));
+    let mut methods = Table::default();
+    methods.insert(Index::Name("close".to_owned()), method(&file, "close", close));
+    methods.insert(Index::Name("lines".to_owned()), method(&file, "lines", lines));
+    methods.insert(Index::Name("read".to_owned()), method(&file, "read", read));
+    methods.insert(Index::Name("write".to_owned()), method(&file, "write", write));
+
+    let mut metatable = Table::default();
+    metatable.insert(Index::Name("__index".to_owned()), Value::Table(Rc::new(RefCell::new(methods))));
+    metatable.insert(Index::Name("__name".to_owned()), Value::String("FILE*".to_owned()));
+
+    let mut handle = Table::default();
+    handle.set_metatable(Some(Rc::new(RefCell::new(metatable))));
+    Ok(vec![Value::Table(Rc::new(RefCell::new(handle)))])
+}
+
+/// Makes a method of a handle for `file`, which still takes the handle as
+/// its first argument, but always uses the file it was made for.
+fn method(file: &OpenFile,
+          name: &'static str,
+          function: fn(&OpenFile, Vec<Value>) -> Result<Vec<Value>>) -> Value {
+    let file = file.clone();
+    Value::NativeClosure(NativeClosure::new(move |arguments| {
+        expect_table(&arguments, 1, name)?;
+        function(&file, arguments)
+    }))
+}
+
+fn io_error(error: std::io::Error) -> LuaError {
+    LuaError::Custom(error.to_string())
+}
+
+/// Runs `f` with the file, if it's not been closed.
+fn with_file<T>(file: &OpenFile, f: impl FnOnce(&mut BufReader<File>) -> Result<T>) -> Result<T> {
+    match file.borrow_mut().as_mut() {
+        Some(file) => f(file),
+        None => Err(LuaError::Custom("attempt to use a closed file".to_owned())),
+    }
+}
+
+fn close(file: &OpenFile, _: Vec<Value>) -> Result<Vec<Value>> {
+    with_file(file, |file| file.get_mut().flush().map_err(io_error))?;
+    file.borrow_mut().take();
+    Ok(vec![Value::Boolean(true)])
+}
+
+/// Writes each string or number argument to the file, giving the handle.
+fn write(file: &OpenFile, arguments: Vec<Value>) -> Result<Vec<Value>> {
+    with_file(file, |file| {
+        for position in 2..=arguments.len() {
+            let s = expect_string(&arguments, position, "write")?;
+            file.get_mut().write_all(s.as_bytes()).map_err(io_error)?;
+        }
+        Ok(())
+    })?;
+
+    Ok(vec![arguments[0].clone()])
+}
+
+fn read_line(file: &mut BufReader<File>, keep_newline: bool) -> Result<Value> {
+    let mut line = String::new();
+    if file.read_line(&mut line).map_err(io_error)? == 0 {
+        return Ok(Value::Nil);
+    }
+
+    if !keep_newline && line.ends_with('\n') {
+        line.pop();
+    }
+    Ok(Value::String(line))
+}
+
+fn read_format(file: &mut BufReader<File>, format: &Value) -> Result<Value> {
+    if let Value::Number(count) = format {
+        let mut bytes = Vec::new();
+        file.by_ref().take(*count as u64).read_to_end(&mut bytes).map_err(io_error)?;
+        return Ok(if bytes.is_empty() && *count > 0.0 {
+            Value::Nil
+        } else {
+            Value::String(String::from_utf8_lossy(&bytes).into_owned())
+        });
+    }
+
+    let format = format.to_lua_string();
+    match format.trim_start_matches('*').chars().next() {
+        Some('l') => read_line(file, false),
+        Some('L') => read_line(file, true),
+        Some('n') => Ok(match read_line(file, false)? {
+            Value::String(line) => value::parse_number(&line).map_or(Value::Nil, Value::Number),
+            _ => Value::Nil,
+        }),
+        Some('a') => {
+            let mut s = String::new();
+            file.read_to_string(&mut s).map_err(io_error)?;
+            Ok(Value::String(s))
+        },
+
+        _ => Err(LuaError::BadArgument(2, "read", "invalid format".to_owned())),
+    }
+}
+
+/// Reads from the file in each of the given formats, which default to a
+/// single line. Reading stops at the first format that gives nil.
+fn read(file: &OpenFile, arguments: Vec<Value>) -> Result<Vec<Value>> {
+    with_file(file, |file| {
+        let default_format = [Value::String("l".to_owned())];
+        let formats = if arguments.len() > 1 { &arguments[1..] } else { &default_format };
+
+        let mut values = Vec::new();
+        for format in formats {
+            let value = read_format(file, format)?;
+            let is_nil = value == Value::Nil;
+            values.push(value);
+            if is_nil {
+                break;
+            }
+        }
+        Ok(values)
+    })
+}
+
+/// Gives an iterator over the lines of the file, for use in a generic `for`.
+fn lines(file: &OpenFile, _: Vec<Value>) -> Result<Vec<Value>> {
+    with_file(file, |_| Ok(()))?;
+
+    let file = file.clone();
+    let next_line = NativeClosure::new(move |_| Ok(vec![with_file(&file, |file| read_line(file, false))?]));
+    Ok(vec![Value::NativeClosure(next_line)])
+}
@@ -0,0 +1,139 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use super::{library, expect_number, expect_integer};
+use super::value::Index;
+use crate::interpreter::{Interpreter, Value, LuaError, Result};
+
+// NOTE: Like the reference implementation, numeric strings are accepted
+//       anywhere a number is expected, and any other value is an error.
+pub fn load(interpreter: &mut Interpreter) {
+    let math = library(&[
+        ("abs", abs),
+        ("ceil", ceil),
+        ("floor", floor),
+        ("max", max),
+        ("min", min),
+        ("random", random),
+        ("randomseed", randomseed),
+        ("sqrt", sqrt),
+    ]);
+
+    if let Value::Table(table) = &math {
+        let mut table = table.borrow_mut();
+        table.insert(Index::Name("huge".to_owned()), Value::Number(f64::INFINITY));
+        table.insert(Index::Name("pi".to_owned()), Value::Number(std::f64::consts::PI));
+    }
+    interpreter.set_global("math", math);
+}
+
+fn abs(_: &mut Interpreter, arguments: Vec<Value>) -> Result<Vec<Value>> {
+    Ok(vec![Value::Number(expect_number(&arguments, 1, "abs")?.abs())])
+}
+
+fn ceil(_: &mut Interpreter, arguments: Vec<Value>) -> Result<Vec<Value>> {
+    Ok(vec![Value::Number(expect_number(&arguments, 1, "ceil")?.ceil())])
+}
+
+fn floor(_: &mut Interpreter, arguments: Vec<Value>) -> Result<Vec<Value>> {
+    Ok(vec![Value::Number(expect_number(&arguments, 1, "floor")?.floor())])
+}
+
+fn sqrt(_: &mut Interpreter, arguments: Vec<Value>) -> Result<Vec<Value>> {
+    Ok(vec![Value::Number(expect_number(&arguments, 1, "sqrt")?.sqrt())])
+}
+
+/// Gives whichever argument comes first by `is_before`. There must be at
+/// least one.
+fn extreme(arguments: &[Value], function: &'static str, is_before: fn(f64, f64) -> bool) -> Result<Vec<Value>> {
+    let mut result = expect_number(arguments, 1, function)?;
+    for position in 2..=arguments.len() {
+        let n = expect_number(arguments, position, function)?;
+        if is_before(n, result) {
+            result = n;
+        }
+    }
+
+    Ok(vec![Value::Number(result)])
+}
+
+fn max(_: &mut Interpreter, arguments: Vec<Value>) -> Result<Vec<Value>> {
+    extreme(&arguments, "max", |a, b| a > b)
+}
+
+fn min(_: &mut Interpreter, arguments: Vec<Value>) -> Result<Vec<Value>> {
+    extreme(&arguments, "min", |a, b| a < b)
+}
+
+// An xorshift64* generator, seeded from the time when first used unless
+// `math.randomseed` was called first. Each interpreter has its own state.
+fn next_random(interpreter: &mut Interpreter) -> u64 {
+    let mut x = interpreter.random_state;
+    if x == 0 {
+        x = SystemTime::now().duration_since(UNIX_EPOCH)
+            .map_or(1, |time| time.as_nanos() as u64 | 1);
+    }
+
+    x ^= x >> 12;
+    x ^= x << 25;
+    x ^= x >> 27;
+    interpreter.random_state = x;
+    x.wrapping_mul(0x2545_F491_4F6C_DD1D)
+}
+
+/// Seeds `math.random` with a number, so the same seed always gives the
+/// same sequence, or with no argument from the time.
+fn randomseed(interpreter: &mut Interpreter, arguments: Vec<Value>) -> Result<Vec<Value>> {
+    let seed = match arguments.first() {
+        None | Some(Value::Nil) => SystemTime::now().duration_since(UNIX_EPOCH)
+            .map_or(0, |time| time.as_nanos() as u64),
+        Some(_) => {
+            let n = expect_number(&arguments, 1, "randomseed")?;
+            if n.fract() == 0.0 { n as i64 as u64 } else { n.to_bits() }
+        },
+    };
+
+    // NOTE: Similar seeds give similar early outputs from xorshift, so the
+    //       seed is mixed with a step of splitmix64 first. The state can't
+    //       be 0, as that would only ever give 0.
+    let mut x = seed.wrapping_add(0x9E37_79B9_7F4A_7C15);
+    x = (x ^ (x >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    x = (x ^ (x >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    interpreter.random_state = (x ^ (x >> 31)).max(1);
+    Ok(Vec::new())
+}
+
+/// Follows Lua 5.4, so with no arguments gives a float in `[0, 1)`, with `m`
+/// gives an integer in `[1, m]` and with `m` and `n` one in `[m, n]`. The
+/// special case `math.random(0)` gives an integer with all bits random.
+fn random(interpreter: &mut Interpreter, arguments: Vec<Value>) -> Result<Vec<Value>> {
+    let (low, high) = match arguments.len() {
+        0 => return Ok(vec![Value::Number((next_random(interpreter) >> 11) as f64 / (1u64 << 53) as f64)]),
+
+        1 => {
+            let high = expect_integer(&arguments, 1, "random")?;
+            if high == 0 {
+                return Ok(vec![Value::Number(next_random(interpreter) as i64 as f64)]);
+            }
+            if high < 1 {
+                return Err(LuaError::BadArgument(1, "random", "interval is empty".to_owned()));
+            }
+
+            (1, high)
+        },
+
+        2 => (expect_integer(&arguments, 1, "random")?, expect_integer(&arguments, 2, "random")?),
+        _ => return Err(LuaError::Custom("wrong number of arguments".to_owned())),
+    };
+
+    if low > high {
+        return Err(LuaError::BadArgument(2, "random", "interval is empty".to_owned()));
+    }
+
+    let span = high.wrapping_sub(low) as u64;
+    let offset = match span.checked_add(1) {
+        Some(count) => next_random(interpreter) % count,
+        None => next_random(interpreter),
+    };
+
+    Ok(vec![Value::Number(low.wrapping_add(offset as i64) as f64)])
+}
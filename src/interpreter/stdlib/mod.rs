@@ -0,0 +1,77 @@
+use std::rc::Rc;
+use std::cell::RefCell;
+
+use super::value::{self, Index, Table, NativeFunction};
+use super::{Interpreter, Value, LuaError, Result};
+
+mod base;
+#[cfg(feature = "io")]
+mod io;
+mod math;
+mod os;
+mod string;
+mod table;
+
+pub fn load(interpreter: &mut Interpreter) {
+    base::load(interpreter);
+    #[cfg(feature = "io")]
+    io::load(interpreter);
+    math::load(interpreter);
+    os::load(interpreter);
+    string::load(interpreter);
+    table::load(interpreter);
+}
+
+fn library(functions: &[(&str, NativeFunction)]) -> Value {
+    let mut table = Table::default();
+    for (name, function) in functions {
+        table.insert(Index::Name(name.to_string()), Value::NativeFunction(*function));
+    }
+
+    Value::Table(Rc::new(RefCell::new(table)))
+}
+
+fn bad_argument_type(position: usize, function: &'static str, expected: &str, got: Option<&Value>) -> LuaError {
+    let got = got.map_or("no value", |value| value.type_name());
+    LuaError::BadArgument(position, function, format!("{} expected, got {}", expected, got))
+}
+
+// NOTE: Argument positions count from 1, matching Lua's error messages.
+fn expect_string(arguments: &[Value], position: usize, function: &'static str) -> Result<String> {
+    match arguments.get(position - 1) {
+        Some(Value::String(s)) => Ok(s.clone()),
+        Some(Value::Number(n)) => Ok(value::format_number(*n)),
+        got => Err(bad_argument_type(position, function, "string", got)),
+    }
+}
+
+fn expect_number(arguments: &[Value], position: usize, function: &'static str) -> Result<f64> {
+    let argument = arguments.get(position - 1);
+    argument
+        .and_then(value::coerce_to_number)
+        .ok_or_else(|| bad_argument_type(position, function, "number", argument))
+}
+
+fn expect_integer(arguments: &[Value], position: usize, function: &'static str) -> Result<i64> {
+    let n = expect_number(arguments, position, function)?;
+    to_integer(n).ok_or_else(|| no_integer_representation(position, function))
+}
+
+/// The integer a number is equal to, if it's a whole number in range.
+fn to_integer(n: f64) -> Option<i64> {
+    // NOTE: 2^63 is just out of range, even though `i64::MAX` converts to
+    //       it as a float. The same check rejects NaN and the infinities.
+    let in_range = n >= i64::MIN as f64 && n < -(i64::MIN as f64);
+    (n.fract() == 0.0 && in_range).then_some(n as i64)
+}
+
+fn no_integer_representation(position: usize, function: &'static str) -> LuaError {
+    LuaError::BadArgument(position, function, "number has no integer representation".to_owned())
+}
+
+fn expect_table(arguments: &[Value], position: usize, function: &'static str) -> Result<Rc<RefCell<Table>>> {
+    match arguments.get(position - 1) {
+        Some(Value::Table(table)) => Ok(table.clone()),
+        got => Err(bad_argument_type(position, function, "table", got)),
+    }
+}
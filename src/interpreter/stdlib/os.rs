@@ -0,0 +1,34 @@
+use std::sync::OnceLock;
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
+use super::library;
+use crate::interpreter::{Interpreter, Value, LuaError, Result};
+
+pub fn load(interpreter: &mut Interpreter) {
+    START.get_or_init(Instant::now);
+    interpreter.set_global("os", library(&[
+        ("clock", clock),
+        ("time", time),
+    ]));
+}
+
+static START: OnceLock<Instant> = OnceLock::new();
+
+// NOTE: The standard library has no way to measure CPU time, so this gives
+//       the time since the library was first loaded in the process instead.
+//       For timing a script that isn't waiting on anything, they're close.
+fn clock(_: &mut Interpreter, _: Vec<Value>) -> Result<Vec<Value>> {
+    let start = START.get_or_init(Instant::now);
+    Ok(vec![Value::Number(start.elapsed().as_secs_f64())])
+}
+
+/// The current time as a whole number of seconds since the Unix epoch.
+fn time(_: &mut Interpreter, arguments: Vec<Value>) -> Result<Vec<Value>> {
+    if !matches!(arguments.first(), None | Some(Value::Nil)) {
+        return Err(LuaError::BadArgument(1, "time", "date tables aren't supported".to_owned()));
+    }
+
+    let seconds = SystemTime::now().duration_since(UNIX_EPOCH)
+        .map_or(0, |time| time.as_secs());
+    Ok(vec![Value::Number(seconds as f64)])
+}
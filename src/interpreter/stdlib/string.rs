@@ -0,0 +1,620 @@
+use std::cell::Cell;
+use std::iter::Peekable;
+use std::str::Chars;
+
+use super::{library, bad_argument_type, expect_string, expect_number, expect_integer, to_integer, no_integer_representation};
+use super::value::{self, Index, NativeClosure};
+use crate::interpreter::pattern::{self, Capture, Match};
+use crate::interpreter::{Interpreter, Value, LuaError, Result};
+
+pub fn load(interpreter: &mut Interpreter) {
+    interpreter.set_global("string", library(&[
+        ("byte", byte),
+        ("char", char_),
+        ("find", find),
+        ("format", format),
+        ("gmatch", gmatch),
+        ("gsub", gsub),
+        ("len", len),
+        ("lower", lower),
+        ("match", match_),
+        ("rep", rep),
+        ("sub", sub),
+        ("upper", upper),
+    ]));
+}
+
+#[derive(Default)]
+struct FormatSpec {
+    left_align: bool,
+    zero_pad: bool,
+    plus_sign: bool,
+    space_sign: bool,
+    alternate: bool,
+    width: usize,
+    precision: Option<usize>,
+}
+
+impl FormatSpec {
+    // NOTE: Like Lua's, the width and precision are at most two digits, so a
+    //       conversion can't ask for an arbitrarily large amount of padding.
+    fn parse(chars: &mut Peekable<Chars>) -> Result<Self> {
+        let mut spec = FormatSpec::default();
+        let mut text = String::new();
+        while let Some(flag) = chars.next_if(|c| "-+ #0".contains(*c)) {
+            text.push(flag);
+            match flag {
+                '-' => spec.left_align = true,
+                '0' => spec.zero_pad = true,
+                '+' => spec.plus_sign = true,
+                ' ' => spec.space_sign = true,
+                _ => spec.alternate = true,
+            }
+        }
+
+        let width = parse_digits(chars);
+        text.push_str(&width);
+        spec.width = width.parse().unwrap_or(0);
+
+        let mut is_too_long = width.len() > 2;
+        if chars.next_if_eq(&'.').is_some() {
+            let precision = parse_digits(chars);
+            text.push('.');
+            text.push_str(&precision);
+            spec.precision = Some(precision.parse().unwrap_or(0));
+            is_too_long |= precision.len() > 2;
+        }
+
+        if is_too_long {
+            text.extend(chars.peek());
+            return Err(LuaError::Custom(format!("invalid conversion '%{}' to 'format'", text)));
+        }
+
+        Ok(spec)
+    }
+
+    fn pad(&self, s: String) -> String {
+        let length = s.chars().count();
+        if length >= self.width {
+            return s;
+        }
+
+        let padding = " ".repeat(self.width - length);
+        if self.left_align {
+            s + &padding
+        } else {
+            padding + &s
+        }
+    }
+
+    fn sign(&self, is_negative: bool) -> &'static str {
+        match is_negative {
+            true => "-",
+            false if self.plus_sign => "+",
+            false if self.space_sign => " ",
+            false => "",
+        }
+    }
+
+    // NOTE: With the `0` flag, zeros go between the sign, or prefix, and
+    //       the digits rather than before both.
+    fn pad_number(&self, prefix: &str, digits: String) -> String {
+        let length = prefix.len() + digits.len();
+        if self.zero_pad && !self.left_align && length < self.width {
+            format!("{}{}{}", prefix, "0".repeat(self.width - length), digits)
+        } else {
+            self.pad(format!("{}{}", prefix, digits))
+        }
+    }
+
+    fn minimum_digits(&self, digits: String) -> String {
+        match self.precision {
+            Some(precision) if digits.len() < precision => "0".repeat(precision - digits.len()) + &digits,
+            _ => digits,
+        }
+    }
+}
+
+fn parse_digits(chars: &mut Peekable<Chars>) -> String {
+    let mut digits = String::new();
+    while let Some(digit) = chars.next_if(char::is_ascii_digit) {
+        digits.push(digit);
+    }
+
+    digits
+}
+
+fn format(interpreter: &mut Interpreter, arguments: Vec<Value>) -> Result<Vec<Value>> {
+    let format_string = expect_string(&arguments, 1, "format")?;
+    let mut chars = format_string.chars().peekable();
+    let mut result = String::new();
+    let mut position = 1;
+
+    while let Some(c) = chars.next() {
+        if c != '%' {
+            result.push(c);
+            continue;
+        }
+
+        if chars.next_if_eq(&'%').is_some() {
+            result.push('%');
+            continue;
+        }
+
+        let spec = FormatSpec::parse(&mut chars)?;
+        position += 1;
+
+        // NOTE: Every conversion needs a value, so a missing one is reported
+        //       the same way whatever its type should have been. Any values
+        //       left over at the end are ignored.
+        if position > arguments.len() {
+            return Err(no_value(position));
+        }
+
+        let formatted = match chars.next() {
+            Some('c') => format_char(&arguments, position)?,
+            Some('d' | 'i') => {
+                // NOTE: Unlike the other integer conversions, fractions are
+                //       truncated towards zero.
+                let n = expect_number(&arguments, position, "format")?.trunc();
+                let n = to_integer(n).ok_or_else(|| no_integer_representation(position, "format"))?;
+                spec.pad_number(spec.sign(n < 0), spec.minimum_digits(n.unsigned_abs().to_string()))
+            },
+            Some(conversion @ ('x' | 'X' | 'o')) =>
+                format_unsigned(&arguments, position, &spec, conversion)?,
+            Some(conversion @ ('f' | 'F' | 'e' | 'E' | 'g' | 'G')) =>
+                format_float(&arguments, position, &spec, conversion)?,
+            Some('q') => format_quoted(&arguments, position)?,
+            Some('s') => format_string_argument(interpreter, &arguments, position, &spec)?,
+
+            Some(conversion) =>
+                return Err(LuaError::Custom(format!("invalid conversion '%{}' to 'format'", conversion))),
+            None =>
+                return Err(LuaError::Custom("invalid conversion '%' to 'format'".to_owned())),
+        };
+
+        result.push_str(&spec.pad(formatted));
+    }
+
+    Ok(vec![Value::String(result)])
+}
+
+fn no_value(position: usize) -> LuaError {
+    LuaError::BadArgument(position, "format", "no value".to_owned())
+}
+
+// NOTE: A byte above 127 is only part of a character, so can't be given on
+//       its own. See `char_`.
+fn format_char(arguments: &[Value], position: usize) -> Result<String> {
+    let code = expect_integer(arguments, position, "format")?;
+    let code = u8::try_from(code)
+        .map_err(|_| LuaError::BadArgument(position, "format", "value out of range".to_owned()))?;
+
+    String::from_utf8(vec![code])
+        .map_err(|_| LuaError::BadArgument(position, "format", "invalid UTF-8".to_owned()))
+}
+
+// NOTE: Negative integers are formatted as their two's complement.
+fn format_unsigned(arguments: &[Value], position: usize, spec: &FormatSpec, conversion: char) -> Result<String> {
+    let n = expect_integer(arguments, position, "format")? as u64;
+    let (digits, prefix) = match conversion {
+        'x' => (format!("{:x}", n), "0x"),
+        'X' => (format!("{:X}", n), "0X"),
+        _ => (format!("{:o}", n), "0"),
+    };
+
+    let prefix = if spec.alternate && n != 0 { prefix } else { "" };
+    Ok(spec.pad_number(prefix, spec.minimum_digits(digits)))
+}
+
+fn format_float(arguments: &[Value], position: usize, spec: &FormatSpec, conversion: char) -> Result<String> {
+    let n = expect_number(arguments, position, "format")?;
+    let sign = spec.sign(n.is_sign_negative() && !n.is_nan());
+    if !n.is_finite() {
+        let digits = if n.is_nan() { "nan" } else { "inf" };
+        let digits = if conversion.is_ascii_uppercase() { digits.to_ascii_uppercase() } else { digits.to_owned() };
+        return Ok(spec.pad(format!("{}{}", sign, digits)));
+    }
+
+    let precision = spec.precision.unwrap_or(6);
+    let digits = match conversion.to_ascii_lowercase() {
+        'f' => format!("{:.*}", precision, n.abs()),
+        'e' => format_exponent(n.abs(), precision),
+        _ => value::format_significant(n.abs(), precision),
+    };
+
+    let digits = if conversion.is_ascii_uppercase() { digits.to_ascii_uppercase() } else { digits };
+    Ok(spec.pad_number(sign, digits))
+}
+
+/// Formats a number the same as C's `%.{precision}e`, which unlike Rust's
+/// always gives the sign of the exponent and at least two of its digits.
+fn format_exponent(n: f64, precision: usize) -> String {
+    let scientific = format!("{:.*e}", precision, n);
+    let (mantissa, exponent) = scientific.split_once('e').unwrap();
+    let exponent = exponent.parse::<i32>().unwrap();
+
+    let sign = if exponent < 0 { '-' } else { '+' };
+    format!("{}e{}{:02}", mantissa, sign, exponent.abs())
+}
+
+/// Formats a value as a Lua literal that reads back as the same value.
+fn format_quoted(arguments: &[Value], position: usize) -> Result<String> {
+    match &arguments[position - 1] {
+        Value::String(s) => {
+            let mut quoted = String::from("\"");
+            let mut chars = s.chars().peekable();
+            while let Some(c) = chars.next() {
+                match c {
+                    '"' => quoted.push_str("\\\""),
+                    '\\' => quoted.push_str("\\\\"),
+                    '\n' => quoted.push_str("\\\n"),
+                    c if c.is_ascii_control() => {
+                        // A following digit would otherwise be read as part of the escape.
+                        if chars.peek().is_some_and(char::is_ascii_digit) {
+                            quoted.push_str(&format!("\\{:03}", c as u32));
+                        } else {
+                            quoted.push_str(&format!("\\{}", c as u32));
+                        }
+                    },
+                    c => quoted.push(c),
+                }
+            }
+
+            quoted.push('"');
+            Ok(quoted)
+        },
+
+        Value::Number(n) if n.is_nan() => Ok("(0/0)".to_owned()),
+        Value::Number(n) if n.is_infinite() => Ok(if *n < 0.0 { "-1e9999" } else { "1e9999" }.to_owned()),
+        Value::Number(n) if n.fract() == 0.0 && n.abs() < 1e15 => Ok(value::format_number(*n)),
+        Value::Number(n) => Ok(value::format_significant(*n, 17)),
+
+        value @ (Value::Nil | Value::Boolean(_)) => Ok(value.to_lua_string()),
+        _ => Err(LuaError::BadArgument(position, "format", "value has no literal form".to_owned())),
+    }
+}
+
+fn format_string_argument(interpreter: &mut Interpreter,
+                          arguments: &[Value],
+                          position: usize,
+                          spec: &FormatSpec) -> Result<String> {
+    let s = interpreter.tostring(&arguments[position - 1])?;
+
+    Ok(match spec.precision {
+        Some(precision) => s.chars().take(precision).collect(),
+        None => s,
+    })
+}
+
+/// `string.sub(s, i, j)` gives the bytes of `s` from `i` to `j`, which
+/// defaults to the end. Negative positions count back from the end.
+fn sub(_: &mut Interpreter, arguments: Vec<Value>) -> Result<Vec<Value>> {
+    let s = expect_string(&arguments, 1, "sub")?;
+    let length = s.len() as i64;
+    let start = expect_integer(&arguments, 2, "sub")?;
+    let end = match arguments.get(2) {
+        None | Some(Value::Nil) => -1,
+        Some(_) => expect_integer(&arguments, 3, "sub")?,
+    };
+
+    let from_end = |position: i64| if position < 0 { (length + position + 1).max(0) } else { position };
+    let start = from_end(start).max(1);
+    let end = from_end(end).min(length);
+    if start > end {
+        return Ok(vec![Value::String(String::new())]);
+    }
+
+    // NOTE: Positions are in bytes, so a non-ASCII character is more than
+    //       one long. Giving part of one would have to replace it, so it's
+    //       an error instead.
+    match s.get(start as usize - 1..end as usize) {
+        Some(s) => Ok(vec![Value::String(s.to_owned())]),
+        None => Err(LuaError::Custom("'sub' can't split a multi-byte character".to_owned())),
+    }
+}
+
+/// The length of a string in bytes, which for non-ASCII text is more than
+/// the number of characters.
+fn len(_: &mut Interpreter, arguments: Vec<Value>) -> Result<Vec<Value>> {
+    Ok(vec![Value::Number(expect_string(&arguments, 1, "len")?.len() as f64)])
+}
+
+/// `string.rep(s, n, sep)` gives `n` copies of `s` separated by `sep`, which
+/// defaults to the empty string.
+fn rep(_: &mut Interpreter, arguments: Vec<Value>) -> Result<Vec<Value>> {
+    let s = expect_string(&arguments, 1, "rep")?;
+    let n = expect_integer(&arguments, 2, "rep")?;
+    let separator = match arguments.get(2) {
+        None | Some(Value::Nil) => String::new(),
+        Some(_) => expect_string(&arguments, 3, "rep")?,
+    };
+
+    if n <= 0 || (s.is_empty() && separator.is_empty()) {
+        return Ok(vec![Value::String(String::new())]);
+    }
+
+    let n = n as usize;
+    let length = s.len().checked_mul(n)
+        .and_then(|length| separator.len().checked_mul(n - 1)?.checked_add(length))
+        .filter(|length| *length <= i32::MAX as usize)
+        .ok_or_else(|| LuaError::Custom("resulting string too large".to_owned()))?;
+
+    let mut result = String::with_capacity(length);
+    for i in 0..n {
+        if i > 0 {
+            result.push_str(&separator);
+        }
+
+        result.push_str(&s);
+    }
+
+    Ok(vec![Value::String(result)])
+}
+
+// NOTE: Only ASCII letters change case, the same as the C locale.
+fn lower(_: &mut Interpreter, arguments: Vec<Value>) -> Result<Vec<Value>> {
+    Ok(vec![Value::String(expect_string(&arguments, 1, "lower")?.to_ascii_lowercase())])
+}
+
+fn upper(_: &mut Interpreter, arguments: Vec<Value>) -> Result<Vec<Value>> {
+    Ok(vec![Value::String(expect_string(&arguments, 1, "upper")?.to_ascii_uppercase())])
+}
+
+/// Gives where to start matching in a string of `length` bytes, from the
+/// optional `init` argument at `position`, or `None` if it's past the end.
+fn start_position(arguments: &[Value], position: usize, function: &'static str, length: usize) -> Result<Option<usize>> {
+    let length = length as i64;
+    let init = match arguments.get(position - 1) {
+        None | Some(Value::Nil) => 1,
+        Some(_) => expect_integer(arguments, position, function)?,
+    };
+
+    let init = match init {
+        init if init < 0 => (length + init + 1).max(1),
+        0 => 1,
+        init => init,
+    };
+    Ok((init <= length + 1).then_some(init as usize - 1))
+}
+
+/// `string.find(s, pattern, init, plain)` gives the start and end positions
+/// of the first match of `pattern` in `s` from `init`, followed by any
+/// captures, or nil if there isn't one. With `plain`, or a pattern without
+/// any special characters, the pattern is found as it is.
+fn find(_: &mut Interpreter, arguments: Vec<Value>) -> Result<Vec<Value>> {
+    let s = expect_string(&arguments, 1, "find")?;
+    let pattern = expect_string(&arguments, 2, "find")?;
+    let Some(start) = start_position(&arguments, 3, "find", s.len())? else {
+        return Ok(vec![Value::Nil]);
+    };
+
+    let plain = arguments.get(3).is_some_and(Value::is_truthy);
+    if plain || !pattern.contains(|c| pattern::SPECIAL_CHARACTERS.contains(c)) {
+        // NOTE: Searching is done on bytes, as `init` may not be on a
+        //       character boundary.
+        let found = s.as_bytes()[start..]
+            .windows(pattern.len().max(1))
+            .position(|window| pattern.is_empty() || window == pattern.as_bytes())
+            .or_else(|| (pattern.is_empty() && start == s.len()).then_some(0));
+
+        return Ok(match found {
+            Some(offset) => {
+                let first = start + offset + 1;
+                vec![Value::Number(first as f64), Value::Number((first + pattern.len() - 1) as f64)]
+            },
+            None => vec![Value::Nil],
+        });
+    }
+
+    Ok(match pattern::find(s.as_bytes(), &pattern, start)? {
+        Some(found) => {
+            let positions = [Value::Number((found.start + 1) as f64), Value::Number(found.end as f64)];
+            let captures = found.captures.iter().map(|capture| capture.to_value(s.as_bytes()));
+            positions.into_iter().chain(captures).collect()
+        },
+        None => vec![Value::Nil],
+    })
+}
+
+/// `string.match(s, pattern, init)` gives the captures of the first match
+/// of `pattern` in `s` from `init`, or nil if there isn't one.
+fn match_(_: &mut Interpreter, arguments: Vec<Value>) -> Result<Vec<Value>> {
+    let s = expect_string(&arguments, 1, "match")?;
+    let pattern = expect_string(&arguments, 2, "match")?;
+    let Some(start) = start_position(&arguments, 3, "match", s.len())? else {
+        return Ok(vec![Value::Nil]);
+    };
+
+    Ok(match pattern::find(s.as_bytes(), &pattern, start)? {
+        Some(found) => found.capture_values(s.as_bytes()),
+        None => vec![Value::Nil],
+    })
+}
+
+/// `string.gmatch(s, pattern, init)` gives an iterator over the captures of
+/// each match of `pattern` in `s`, for use in a generic `for`.
+fn gmatch(_: &mut Interpreter, arguments: Vec<Value>) -> Result<Vec<Value>> {
+    let s = expect_string(&arguments, 1, "gmatch")?;
+    let pattern = expect_string(&arguments, 2, "gmatch")?;
+    let start = start_position(&arguments, 3, "gmatch", s.len())?.unwrap_or(s.len() + 1);
+
+    // NOTE: An empty match straight after the last match is skipped, so the
+    //       iterator always moves forwards.
+    let position = Cell::new(start);
+    let last_match = Cell::new(None);
+    let iterator = NativeClosure::new(move |_| {
+        for start in position.get()..=s.len() {
+            if let Some(found) = pattern::match_at(s.as_bytes(), &pattern, start)? {
+                if last_match.get() != Some(found.end) {
+                    position.set(found.end);
+                    last_match.set(Some(found.end));
+                    return Ok(found.capture_values(s.as_bytes()));
+                }
+            }
+        }
+
+        position.set(s.len() + 1);
+        Ok(vec![Value::Nil])
+    });
+
+    Ok(vec![Value::NativeClosure(iterator)])
+}
+
+/// `string.gsub(s, pattern, replacement, n)` replaces the first `n`, or all,
+/// matches of `pattern` in `s`, giving the result and how many were made.
+/// The replacement may be a string, where `%1` to `%9` are captures and `%0`
+/// is the whole match, a table indexed by the first capture, or a function
+/// called with every capture. If a table or function gives nil or false the
+/// match is kept as it is.
+fn gsub(interpreter: &mut Interpreter, arguments: Vec<Value>) -> Result<Vec<Value>> {
+    let s = expect_string(&arguments, 1, "gsub")?;
+    let pattern = expect_string(&arguments, 2, "gsub")?;
+    let replacement = match arguments.get(2) {
+        Some(Value::Number(n)) => Value::String(value::format_number(*n)),
+        Some(replacement @ (Value::String(_) | Value::Table(_)
+            | Value::Function(_) | Value::NativeFunction(_) | Value::NativeClosure(_))) => replacement.clone(),
+        got => return Err(bad_argument_type(3, "gsub", "string/function/table", got)),
+    };
+    let max_replacements = match arguments.get(3) {
+        None | Some(Value::Nil) => None,
+        Some(_) => Some(expect_integer(&arguments, 4, "gsub")?),
+    };
+
+    let subject = s.as_bytes();
+    let (anchored, pattern) = pattern::split_anchor(&pattern);
+    let mut result = Vec::new();
+    let mut position = 0;
+    let mut last_match = None;
+    let mut count = 0;
+
+    while max_replacements.is_none_or(|max_replacements| count < max_replacements) {
+        match pattern::match_at(subject, pattern, position)? {
+            Some(found) if last_match != Some(found.end) => {
+                count += 1;
+                result.extend(replace(interpreter, subject, &found, &replacement)?);
+                position = found.end;
+                last_match = Some(found.end);
+            },
+
+            _ if position < subject.len() => {
+                result.push(subject[position]);
+                position += 1;
+            },
+            _ => break,
+        }
+
+        if anchored {
+            break;
+        }
+    }
+
+    result.extend_from_slice(&subject[position..]);
+    Ok(vec![Value::String(String::from_utf8_lossy(&result).into_owned()), Value::Number(count as f64)])
+}
+
+fn capture_bytes(subject: &[u8], capture: Capture) -> Vec<u8> {
+    match capture {
+        Capture::Substring(start, end) => subject[start..end].to_vec(),
+        Capture::Position(position) => value::format_number((position + 1) as f64).into_bytes(),
+    }
+}
+
+fn replace(interpreter: &mut Interpreter, subject: &[u8], found: &Match, replacement: &Value) -> Result<Vec<u8>> {
+    let whole_match = &subject[found.start..found.end];
+    let replaced = match replacement {
+        Value::String(replacement) => {
+            let mut result = Vec::new();
+            let mut bytes = replacement.bytes();
+            while let Some(c) = bytes.next() {
+                if c != b'%' {
+                    result.push(c);
+                    continue;
+                }
+
+                match bytes.next() {
+                    Some(b'%') => result.push(b'%'),
+                    Some(b'0') => result.extend_from_slice(whole_match),
+                    Some(digit @ b'1'..=b'9') => {
+                        let index = (digit - b'1') as usize;
+                        let capture = found.capture(index).ok_or_else(||
+                            LuaError::Custom(format!("invalid capture index %{} in replacement string", index + 1)))?;
+                        result.extend(capture_bytes(subject, capture));
+                    },
+                    _ => return Err(LuaError::Custom("invalid use of '%' in replacement string".to_owned())),
+                }
+            }
+
+            return Ok(result);
+        },
+
+        Value::Table(table) => {
+            let key = found.capture_values(subject).swap_remove(0);
+            match Index::from_value(key) {
+                Some(index) => interpreter.get_from_table(table, &index)?,
+                None => Value::Nil,
+            }
+        },
+
+        function => {
+            let captures = found.capture_values(subject);
+            interpreter.call(function.clone(), captures)?.into_iter().next().unwrap_or(Value::Nil)
+        },
+    };
+
+    match replaced {
+        Value::Nil | Value::Boolean(false) => Ok(whole_match.to_vec()),
+        Value::String(s) => Ok(s.into_bytes()),
+        Value::Number(n) => Ok(value::format_number(n).into_bytes()),
+        value => Err(LuaError::Custom(format!("invalid replacement value (a {})", value.type_name()))),
+    }
+}
+
+/// `string.byte(s, i, j)` gives the bytes of `s` from `i`, which defaults to
+/// 1, to `j`, which defaults to `i`, as numbers.
+fn byte(_: &mut Interpreter, arguments: Vec<Value>) -> Result<Vec<Value>> {
+    let s = expect_string(&arguments, 1, "byte")?;
+    let length = s.len() as i64;
+    let start = match arguments.get(1) {
+        None | Some(Value::Nil) => 1,
+        Some(_) => expect_integer(&arguments, 2, "byte")?,
+    };
+    let end = match arguments.get(2) {
+        None | Some(Value::Nil) => start,
+        Some(_) => expect_integer(&arguments, 3, "byte")?,
+    };
+
+    let from_end = |position: i64| if position < 0 { (length + position + 1).max(0) } else { position };
+    let start = from_end(start).max(1);
+    let end = from_end(end).min(length);
+    if start > end {
+        return Ok(Vec::new());
+    }
+
+    Ok(s.as_bytes()[start as usize - 1..end as usize].iter()
+        .map(|byte| Value::Number(*byte as f64))
+        .collect())
+}
+
+/// `string.char(...)` gives the string made of the given bytes, which must
+/// be valid UTF-8, such as `string.char(195, 169)` for "é". This is the
+/// reverse of `byte`, which gives the UTF-8 bytes of each character.
+fn char_(_: &mut Interpreter, arguments: Vec<Value>) -> Result<Vec<Value>> {
+    let bytes = (1..=arguments.len())
+        .map(|position| {
+            let code = expect_integer(&arguments, position, "char")?;
+            u8::try_from(code)
+                .map_err(|_| LuaError::BadArgument(position, "char", "value out of range".to_owned()))
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    // NOTE: Each byte is its own argument, so the first that isn't part of
+    //       a valid character is the one to blame.
+    match String::from_utf8(bytes) {
+        Ok(s) => Ok(vec![Value::String(s)]),
+        Err(error) => {
+            let position = error.utf8_error().valid_up_to() + 1;
+            Err(LuaError::BadArgument(position, "char", "invalid UTF-8".to_owned()))
+        },
+    }
+}
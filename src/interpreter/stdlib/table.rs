@@ -0,0 +1,162 @@
+use std::cmp::Ordering;
+
+use super::{library, bad_argument_type, expect_integer, expect_string, expect_table};
+use crate::interpreter::value::{self, Index};
+use crate::interpreter::{Interpreter, Value, LuaError, Result};
+
+pub fn load(interpreter: &mut Interpreter) {
+    interpreter.set_global("table", library(&[
+        ("concat", concat),
+        ("insert", insert),
+        ("remove", remove),
+        ("sort", sort),
+    ]));
+}
+
+fn position_out_of_bounds(position: usize, function: &'static str) -> LuaError {
+    LuaError::BadArgument(position, function, "position out of bounds".to_owned())
+}
+
+/// `table.concat(t, sep, i, j)` joins the strings and numbers from `t[i]` to
+/// `t[j]` with `sep` between each. These default to `""`, `1` and `#t`.
+fn concat(_: &mut Interpreter, arguments: Vec<Value>) -> Result<Vec<Value>> {
+    let table = expect_table(&arguments, 1, "concat")?;
+    let is_given = |position: usize| arguments.get(position - 1).is_some_and(|value| *value != Value::Nil);
+
+    let separator = if is_given(2) { expect_string(&arguments, 2, "concat")? } else { String::new() };
+    let first = if is_given(3) { expect_integer(&arguments, 3, "concat")? } else { 1 };
+    let last = if is_given(4) { expect_integer(&arguments, 4, "concat")? } else { table.borrow().length() };
+
+    let table = table.borrow();
+    let mut result = String::new();
+    for i in first..=last {
+        match table.get(&Index::Number(i)) {
+            Some(Value::String(s)) => result.push_str(s),
+            Some(Value::Number(n)) => result.push_str(&value::format_number(*n)),
+            _ => return Err(LuaError::Custom(format!("invalid value (at index {}) in table for 'concat'", i))),
+        }
+
+        if i != last {
+            result.push_str(&separator);
+        }
+    }
+
+    Ok(vec![Value::String(result)])
+}
+
+/// `table.insert(t, value)` appends to the end of the sequence, and
+/// `table.insert(t, pos, value)` inserts at `pos`, moving the elements
+/// from `pos` onwards up by one.
+fn insert(_: &mut Interpreter, arguments: Vec<Value>) -> Result<Vec<Value>> {
+    let table = expect_table(&arguments, 1, "insert")?;
+    let end = table.borrow().length() + 1;
+
+    let (position, value) = match arguments.len() {
+        2 => (end, arguments[1].clone()),
+        3 => {
+            let position = expect_integer(&arguments, 2, "insert")?;
+            if !(1..=end).contains(&position) {
+                return Err(position_out_of_bounds(2, "insert"));
+            }
+
+            (position, arguments[2].clone())
+        },
+        _ => return Err(LuaError::Custom("wrong number of arguments to 'insert'".to_owned())),
+    };
+
+    let mut table = table.borrow_mut();
+    for i in (position..end).rev() {
+        let moved = table.get(&Index::Number(i)).cloned().unwrap_or(Value::Nil);
+        table.insert(Index::Number(i + 1), moved);
+    }
+
+    table.insert(Index::Number(position), value);
+    Ok(Vec::new())
+}
+
+/// `table.remove(t, pos)` removes and returns the element at `pos`, which
+/// defaults to the end of the sequence, moving the elements after it down
+/// by one.
+fn remove(_: &mut Interpreter, arguments: Vec<Value>) -> Result<Vec<Value>> {
+    let table = expect_table(&arguments, 1, "remove")?;
+    let size = table.borrow().length();
+
+    let mut position = match arguments.get(1) {
+        Some(_) => expect_integer(&arguments, 2, "remove")?,
+        None => size,
+    };
+
+    // NOTE: As in the reference implementation, the position just past the
+    //       end may be removed too, as may 0 when the table is empty.
+    if position != size && !(1..=size + 1).contains(&position) {
+        return Err(position_out_of_bounds(2, "remove"));
+    }
+
+    let mut table = table.borrow_mut();
+    let removed = table.get(&Index::Number(position)).cloned().unwrap_or(Value::Nil);
+    while position < size {
+        let moved = table.get(&Index::Number(position + 1)).cloned().unwrap_or(Value::Nil);
+        table.insert(Index::Number(position), moved);
+        position += 1;
+    }
+
+    table.insert(Index::Number(position), Value::Nil);
+    Ok(vec![removed])
+}
+
+/// `table.sort(t, comp)` sorts the sequence in `t` in place, using `<` or
+/// `comp(a, b)`, which should be true when `a` must come before `b`.
+///
+/// NOTE: This is a merge sort rather than the standard library's, which may
+///       panic if the order function isn't consistent. An inconsistent one
+///       here just gives some unspecified order. Any error it raises stops
+///       the sort, leaving the table as it was.
+fn sort(interpreter: &mut Interpreter, arguments: Vec<Value>) -> Result<Vec<Value>> {
+    let table = expect_table(&arguments, 1, "sort")?;
+    let comparator = match arguments.get(1) {
+        None | Some(Value::Nil) => None,
+        Some(function @ (Value::Function(_) | Value::NativeFunction(_) | Value::NativeClosure(_))) => Some(function.clone()),
+        got => return Err(bad_argument_type(2, "sort", "function", got)),
+    };
+
+    let length = table.borrow().length();
+    let values = (1..=length)
+        .map(|i| table.borrow().get(&Index::Number(i)).cloned().unwrap_or(Value::Nil))
+        .collect();
+
+    let mut less = |a: &Value, b: &Value| match &comparator {
+        Some(comparator) => Ok(interpreter.call(comparator.clone(), vec![a.clone(), b.clone()])?
+            .first().is_some_and(Value::is_truthy)),
+        None => Ok(value::execute_comparison_operation(a.clone(), b.clone(), Ordering::is_lt)?.is_truthy()),
+    };
+
+    let sorted = merge_sort(values, &mut less)?;
+    let mut table = table.borrow_mut();
+    for (i, value) in sorted.into_iter().enumerate() {
+        table.insert(Index::Number(i as i64 + 1), value);
+    }
+
+    Ok(Vec::new())
+}
+
+fn merge_sort(mut values: Vec<Value>, less: &mut impl FnMut(&Value, &Value) -> Result<bool>) -> Result<Vec<Value>> {
+    if values.len() <= 1 {
+        return Ok(values);
+    }
+
+    let right = values.split_off(values.len() / 2);
+    let mut left = merge_sort(values, less)?.into_iter().peekable();
+    let mut right = merge_sort(right, less)?.into_iter().peekable();
+
+    // NOTE: Taking from the left unless the right is strictly less keeps
+    //       equal elements in their original order.
+    let mut merged = Vec::with_capacity(left.len() + right.len());
+    while let (Some(a), Some(b)) = (left.peek(), right.peek()) {
+        let next = if less(b, a)? { right.next() } else { left.next() };
+        merged.extend(next);
+    }
+
+    merged.extend(left);
+    merged.extend(right);
+    Ok(merged)
+}
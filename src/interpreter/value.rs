@@ -1,19 +1,193 @@
 use crate::ast::Statement;
 use std::collections::HashMap;
 use std::rc::Rc;
-use std::cell::RefCell;
+use std::cell::{Cell, RefCell};
+use std::cmp::Ordering;
 use core::fmt;
 
 use super::error::LuaError;
-use super::Result;
+use super::{Interpreter, Result};
 
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub enum Index {
     Name(String),
-    Number(i32),
+    Number(i64),
+
+    // The bits of any other number, as `f64` can't be hashed.
+    Float(u64),
+
+    Boolean(bool),
+
+    // A table or function, which is only ever equal to itself.
+    Reference(Reference),
 }
 
-pub type Table = HashMap<Index, Value>;
+/// A table or function used as an index, compared and hashed by its address
+/// rather than its contents.
+#[derive(Debug, Clone)]
+pub struct Reference(Value);
+
+impl Reference {
+    fn address(&self) -> *const () {
+        match &self.0 {
+            Value::Table(table) => Rc::as_ptr(table) as *const (),
+            Value::Function(function) => Rc::as_ptr(function) as *const (),
+            Value::NativeFunction(function) => *function as *const (),
+            Value::NativeClosure(function) => Rc::as_ptr(&function.0) as *const (),
+            _ => unreachable!("only tables and functions are references"),
+        }
+    }
+}
+
+impl PartialEq for Reference {
+    fn eq(&self, other: &Self) -> bool {
+        self.address() == other.address()
+    }
+}
+
+impl Eq for Reference {}
+
+impl std::hash::Hash for Reference {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        self.address().hash(state);
+    }
+}
+
+impl Index {
+    pub fn from_number(n: f64) -> Self {
+        // NOTE: Only integers that fit in an `i64` get a numeric index, anything
+        //       else (fractions, huge values, infinities) would be silently
+        //       truncated or saturated by the cast, colliding with other keys.
+        //       Integer valued floats are normalised this way, so `t[2]` and
+        //       `t[2.0]` are the same slot.
+        if f64::trunc(n) == n && n >= i64::MIN as f64 && n < i64::MAX as f64 {
+            Index::Number(n as i64)
+        } else {
+            Index::Float(n.to_bits())
+        }
+    }
+
+    /// The index for a value, which is any value other than nil or NaN.
+    pub fn from_value(value: Value) -> Option<Self> {
+        match value {
+            Value::Nil => None,
+            Value::Number(n) if n.is_nan() => None,
+            Value::Number(n) => Some(Index::from_number(n)),
+            Value::String(s) => Some(Index::Name(s)),
+            Value::Boolean(b) => Some(Index::Boolean(b)),
+            value @ (Value::Table(_) | Value::Function(_) | Value::NativeFunction(_) | Value::NativeClosure(_)) =>
+                Some(Index::Reference(Reference(value))),
+        }
+    }
+
+    pub fn to_value(&self) -> Value {
+        match self {
+            Index::Name(name) => Value::String(name.clone()),
+            Index::Number(n) => Value::Number(*n as f64),
+            Index::Float(bits) => Value::Number(f64::from_bits(*bits)),
+            Index::Boolean(b) => Value::Boolean(*b),
+            Index::Reference(Reference(value)) => value.clone(),
+        }
+    }
+}
+
+#[derive(Default, Debug, Clone)]
+pub struct Table {
+    items: HashMap<Index, Value>,
+
+    // NOTE: Finding the border is linear in the length of the sequence, so
+    //       it's cached until the next write to a numeric index.
+    length: Cell<Option<i64>>,
+
+    // NOTE: `next` would be linear in the size of the table if it searched
+    //       the map for the previous key, so the keys are kept in iteration
+    //       order, along with where the last key given was, until a new key
+    //       is added.
+    keys: RefCell<Option<Vec<Index>>>,
+    last_next: Cell<usize>,
+
+    metatable: Option<Rc<RefCell<Table>>>,
+}
+
+impl PartialEq for Table {
+    fn eq(&self, other: &Self) -> bool {
+        self.items == other.items
+    }
+}
+
+impl Table {
+    pub fn get(&self, index: &Index) -> Option<&Value> {
+        self.items.get(index)
+    }
+
+    pub fn insert(&mut self, index: Index, value: Value) {
+        if let Index::Number(_) = index {
+            self.length.set(None);
+        }
+
+        if value == Value::Nil {
+            self.items.remove(&index);
+        } else if self.items.insert(index, value).is_none() {
+            *self.keys.get_mut() = None;
+        }
+    }
+
+    /// Gives the key and value that come after `after` in the table, or the
+    /// first if it's `None`, in an unspecified order. Fields may be cleared
+    /// during a traversal, but adding a new key may make it skip or repeat
+    /// keys.
+    pub fn next(&self, after: Option<&Index>) -> Result<Option<(Index, Value)>> {
+        let mut keys = self.keys.borrow_mut();
+        let keys = keys.get_or_insert_with(|| self.items.keys().cloned().collect());
+
+        let start = match after {
+            None => 0,
+            Some(index) => {
+                let last_next = self.last_next.get();
+                let position = if keys.get(last_next) == Some(index) {
+                    last_next
+                } else {
+                    keys.iter().position(|key| key == index)
+                        .ok_or_else(|| LuaError::Custom("invalid key to 'next'".to_owned()))?
+                };
+                position + 1
+            },
+        };
+
+        for (position, key) in keys.iter().enumerate().skip(start) {
+            if let Some(value) = self.items.get(key) {
+                self.last_next.set(position);
+                return Ok(Some((key.clone(), value.clone())));
+            }
+        }
+
+        Ok(None)
+    }
+
+    pub fn metatable(&self) -> Option<Rc<RefCell<Table>>> {
+        self.metatable.clone()
+    }
+
+    pub fn set_metatable(&mut self, metatable: Option<Rc<RefCell<Table>>>) {
+        self.metatable = metatable;
+    }
+
+    /// Finds the border of the table's sequence, the largest `n` such that
+    /// every index from `1` to `n` holds a non-nil value.
+    pub fn length(&self) -> i64 {
+        if let Some(length) = self.length.get() {
+            return length;
+        }
+
+        let mut length = 0;
+        while self.items.contains_key(&Index::Number(length + 1)) {
+            length += 1;
+        }
+
+        self.length.set(Some(length));
+        length
+    }
+}
 
 #[derive(Debug, PartialEq, Clone)]
 pub struct FunctionCapture {
@@ -22,7 +196,56 @@ pub struct FunctionCapture {
     pub capture: Scope,
 }
 
+pub type NativeFunction = fn(&mut Interpreter, Vec<Value>) -> Result<Vec<Value>>;
+
+/// A native function that may capture state, such as those registered with
+/// `Interpreter::define_fn` or iterators made by the standard library.
+#[derive(Clone)]
+pub struct NativeClosure(Rc<dyn Fn(Vec<Value>) -> Result<Vec<Value>>>);
+
+impl NativeClosure {
+    pub fn new(function: impl Fn(Vec<Value>) -> Result<Vec<Value>> + 'static) -> Self {
+        NativeClosure(Rc::new(function))
+    }
+
+    pub fn call(&self, arguments: Vec<Value>) -> Result<Vec<Value>> {
+        (self.0)(arguments)
+    }
+}
+
+impl fmt::Debug for NativeClosure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "NativeClosure({:p})", Rc::as_ptr(&self.0) as *const ())
+    }
+}
+
+impl PartialEq for NativeClosure {
+    fn eq(&self, other: &Self) -> bool {
+        Rc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+/// Functions that can be registered with `Interpreter::define_fn`, which
+/// take either no arguments or all of them, and give anything that can be
+/// converted into a value.
+pub trait IntoNativeClosure<Arguments> {
+    fn into_native_closure(self) -> NativeClosure;
+}
+
+impl<F: Fn() -> R + 'static, R: Into<Value>> IntoNativeClosure<()> for F {
+    fn into_native_closure(self) -> NativeClosure {
+        NativeClosure::new(move |_| Ok(vec![self().into()]))
+    }
+}
+
+impl<F: Fn(Vec<Value>) -> R + 'static, R: Into<Value>> IntoNativeClosure<Vec<Value>> for F {
+    fn into_native_closure(self) -> NativeClosure {
+        NativeClosure::new(move |arguments| Ok(vec![self(arguments).into()]))
+    }
+}
+
 #[derive(Debug, PartialEq, Clone)]
+#[allow(unpredictable_function_pointer_comparisons)]
 pub enum Value {
     Nil,
     Number(f64),
@@ -30,20 +253,46 @@ pub enum Value {
     Boolean(bool),
     Function(Rc<FunctionCapture>),
     Table(Rc<RefCell<Table>>),
-    NativeFunction(fn(Vec<Value>) -> Value),
+    NativeFunction(NativeFunction),
+    NativeClosure(NativeClosure),
 }
 
+impl From<f64> for Value {
+    fn from(n: f64) -> Self {
+        Value::Number(n)
+    }
+}
+
+impl From<bool> for Value {
+    fn from(b: bool) -> Self {
+        Value::Boolean(b)
+    }
+}
+
+impl From<String> for Value {
+    fn from(s: String) -> Self {
+        Value::String(s)
+    }
+}
+
+impl From<&str> for Value {
+    fn from(s: &str) -> Self {
+        Value::String(s.to_owned())
+    }
+}
+
+impl From<()> for Value {
+    fn from(_: ()) -> Self {
+        Value::Nil
+    }
+}
+
+// NOTE: Displaying a value can't call its `__tostring` metamethod, as that
+//       needs an interpreter to run it, so use `Interpreter::tostring` for
+//       anything shown to users.
 impl fmt::Display for Value {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        match self {
-            Value::Nil => write!(f, "<nil>"),
-            Value::Number(n) => write!(f, "{}", n),
-            Value::String(s) => write!(f, "{}", s),
-            Value::Boolean(b) => write!(f, "{}", b),
-            Value::Table(table) => write!(f, "{:?}", table.borrow()),
-            Value::Function(_) => write!(f, "<function>"),
-            Value::NativeFunction(_) => write!(f, "<native function>"),
-        }
+        write!(f, "{}", self.to_lua_string())
     }
 }
 
@@ -54,25 +303,64 @@ impl Value {
             Self::Number(_) => "number",
             Self::String(_) => "string",
             Self::Boolean(_) => "boolean",
-            Self::Function(_) | Self::NativeFunction(_) => "function",
+            Self::Function(_) | Self::NativeFunction(_) | Self::NativeClosure(_) => "function",
             Self::Table(_) => "table",
         }
     }
 
-    pub fn is_truthy(&self) -> bool {
+    /// Converts the value to a string, the way Lua's `tostring` would, other
+    /// than calling `__tostring`. A table whose metatable has a `__name`
+    /// field is shown with that in place of `table`.
+    pub fn to_lua_string(&self) -> String {
         match self {
-            Self::Boolean(false) | Self::Nil => false,
-            _ => true,
+            Value::Nil => "nil".to_owned(),
+            Value::Number(n) => format_number(*n),
+            Value::String(s) => s.clone(),
+            Value::Boolean(b) => b.to_string(),
+            Value::Table(table) => {
+                let name = table.borrow().metatable()
+                    .and_then(|metatable| match metatable.borrow().get(&Index::Name("__name".to_owned())) {
+                        Some(Value::String(name)) => Some(name.clone()),
+                        _ => None,
+                    });
+                format!("{}: {:p}", name.as_deref().unwrap_or("table"), Rc::as_ptr(table))
+            },
+            Value::Function(function) => format!("function: {:p}", Rc::as_ptr(function)),
+            Value::NativeFunction(function) => format!("function: {:p}", *function as *const ()),
+            Value::NativeClosure(function) => format!("function: {:p}", Rc::as_ptr(&function.0) as *const ()),
         }
     }
+
+    pub fn is_truthy(&self) -> bool {
+        !matches!(self, Self::Boolean(false) | Self::Nil)
+    }
 }
 
 #[derive(Default, Debug, PartialEq, Clone)]
 pub struct Scope {
     table: HashMap<String, Rc<RefCell<Value>>>,
+
+    // The extra arguments passed to the enclosing function, if it's variadic.
+    pub varargs: Option<Vec<Value>>,
+
+    // Whether reading a name that isn't a local gives nil, rather than the
+    // global of that name.
+    pub isolated: bool,
+
+    // The values of `<close>` locals in scope, in the order they were
+    // declared, which are closed when the block declaring them exits.
+    pub to_close: Vec<Value>,
 }
 
+// NOTE: Cloning a scope shares the cells of its variables, so a block or
+//       closure given a clone sees, and can assign to, the variables of its
+//       enclosing scope. Declaring a local always creates a new cell instead,
+//       which shadows any variable with the same name from the clone on.
 impl Scope {
+    pub fn declare(&mut self, name: String, value: Value) {
+        self.table.insert(name, Rc::from(RefCell::from(value)));
+    }
+
     pub fn put(&mut self, name: String, value: Value) {
         match self.table.get(&name) {
             Some(slot) => slot.swap(&RefCell::from(value)),
@@ -89,46 +377,199 @@ impl Scope {
     }
 }
 
+/// Formats a number the same as Lua's `%.14g`, so integral values don't
+/// have a decimal point and others have at most 14 significant digits.
+pub fn format_number(n: f64) -> String {
+    format_significant(n, 14)
+}
+
+/// Formats a number the same as C's `%.{digits}g`.
+pub fn format_significant(n: f64, digits: usize) -> String {
+    if n.is_nan() {
+        return if n.is_sign_negative() { "-nan" } else { "nan" }.to_owned();
+    }
+
+    if n.is_infinite() {
+        return if n < 0.0 { "-inf" } else { "inf" }.to_owned();
+    }
+
+    // NOTE: Rounding to 14 significant digits first gives the exponent of
+    //       the result, which decides between fixed and scientific notation.
+    let digits = digits.max(1);
+    let scientific = format!("{:.*e}", digits - 1, n);
+    let (mantissa, exponent) = scientific.split_once('e').unwrap();
+    let exponent = exponent.parse::<i32>().unwrap();
+
+    if !(-4..digits as i32).contains(&exponent) {
+        let sign = if exponent < 0 { '-' } else { '+' };
+        format!("{}e{}{:02}", trim_fraction(mantissa), sign, exponent.abs())
+    } else {
+        let precision = (digits as i32 - 1 - exponent) as usize;
+        trim_fraction(&format!("{:.*}", precision, n)).to_owned()
+    }
+}
+
+fn trim_fraction(s: &str) -> &str {
+    if s.contains('.') {
+        s.trim_end_matches('0').trim_end_matches('.')
+    } else {
+        s
+    }
+}
+
+pub fn coerce_to_number(value: &Value) -> Option<f64> {
+    match value {
+        Value::Number(n) => Some(*n),
+        Value::String(s) => parse_number(s),
+        _ => None,
+    }
+}
+
+/// Parses a string the way Lua reads a numeral, allowing surrounding
+/// whitespace, a sign, exponents, and hexadecimal such as `0xff` or `0x1p4`.
+pub fn parse_number(s: &str) -> Option<f64> {
+    let s = s.trim();
+    let (negative, unsigned) = match s.strip_prefix('-') {
+        Some(rest) => (true, rest),
+        None => (false, s.strip_prefix('+').unwrap_or(s)),
+    };
+
+    let n = match unsigned.strip_prefix("0x").or_else(|| unsigned.strip_prefix("0X")) {
+        Some(digits) => parse_hex_number(digits)?,
+
+        // NOTE: Rust also accepts words like `inf` and `nan`, which aren't
+        //       numerals in Lua.
+        None if unsigned.starts_with(|c: char| c.is_ascii_digit() || c == '.') =>
+            unsigned.parse::<f64>().ok()?,
+
+        None => return None,
+    };
+
+    Some(if negative { -n } else { n })
+}
+
+// NOTE: Hexadecimal numbers can have a fraction, and a binary exponent
+//       after a `p`, so `0x1.8p1` is `1.5 * 2^1`.
+fn parse_hex_number(digits: &str) -> Option<f64> {
+    let (mantissa, exponent) = match digits.split_once(['p', 'P']) {
+        Some((mantissa, exponent)) => (mantissa, parse_binary_exponent(exponent)?),
+        None => (digits, 0),
+    };
+
+    let (integer, fraction) = mantissa.split_once('.').unwrap_or((mantissa, ""));
+    if integer.is_empty() && fraction.is_empty() {
+        return None;
+    }
+
+    let mut n = 0.0;
+    for c in integer.chars() {
+        n = n * 16.0 + c.to_digit(16)? as f64;
+    }
+
+    let mut scale = 1.0 / 16.0;
+    for c in fraction.chars() {
+        n += c.to_digit(16)? as f64 * scale;
+        scale /= 16.0;
+    }
+
+    if n == 0.0 {
+        return Some(0.0);
+    }
+    Some(n * 2f64.powi(exponent))
+}
+
+// NOTE: An exponent too big for an `i32` already puts any non-zero
+//       mantissa past inf or below the smallest float, so it saturates.
+fn parse_binary_exponent(exponent: &str) -> Option<i32> {
+    let (negative, digits) = match exponent.strip_prefix('-') {
+        Some(rest) => (true, rest),
+        None => (false, exponent.strip_prefix('+').unwrap_or(exponent)),
+    };
+    if digits.is_empty() || !digits.chars().all(|c| c.is_ascii_digit()) {
+        return None;
+    }
+
+    let magnitude = digits.parse::<i32>().unwrap_or(i32::MAX);
+    Some(if negative { -magnitude } else { magnitude })
+}
+
 pub fn execute_arithmetic_operation(lhs: Value,
                                     rhs: Value,
                                     number_operation: fn(f64, f64) -> f64) -> Result<Value> {
-    match lhs {
-        Value::Nil => Err(LuaError::InvalidArithmetic(lhs)),
-        Value::Number(lhs_n) => match rhs {
-            Value::Nil => Err(LuaError::InvalidArithmetic(rhs)),
-            Value::Number(rhs_n) => Ok(Value::Number(number_operation(lhs_n, rhs_n))),
-            Value::String(_) => todo!("Implement string, number operations"),
-            Value::Boolean(_) => Err(LuaError::InvalidArithmetic(rhs)),
-            Value::Table(_) => Err(LuaError::InvalidArithmetic(rhs)),
-            Value::Function(_) => Err(LuaError::InvalidArithmetic(rhs)),
-            Value::NativeFunction(_) => Err(LuaError::InvalidArithmetic(rhs)),
-        },
-        Value::String(_) => todo!("Implement string, string operations"),
-        Value::Boolean(_) => Err(LuaError::InvalidArithmetic(lhs)),
-        Value::Table(_) => Err(LuaError::InvalidArithmetic(lhs)),
-        Value::Function(_) => Err(LuaError::InvalidArithmetic(lhs)),
-        Value::NativeFunction(_) => Err(LuaError::InvalidArithmetic(lhs)),
-    }
-}
-
-pub fn execute_logic_operation(lhs: Value,
-                               rhs: Value,
-                               number_operation: fn(f64, f64) -> bool) -> Value {
-    match lhs {
-        Value::Nil => Value::Nil,
-        Value::Number(lhs_n) => match rhs {
-            Value::Nil => Value::Nil,
-            Value::Number(rhs_n) => Value::Boolean(number_operation(lhs_n, rhs_n)),
-            Value::String(_) => Value::Nil,
-            Value::Boolean(_) => Value::Nil,
-            Value::Table(_) => Value::Nil,
-            Value::Function(_) => Value::Nil,
-            Value::NativeFunction(_) => Value::Nil,
-        },
-        Value::String(_) => Value::Nil,
-        Value::Boolean(_) => Value::Nil,
-        Value::Table(_) => Value::Nil,
-        Value::Function(_) => Value::Nil,
-        Value::NativeFunction(_) => Value::Nil,
+    let lhs_n = match coerce_to_number(&lhs) {
+        Some(n) => n,
+        None => return Err(LuaError::InvalidArithmetic(lhs)),
+    };
+
+    let rhs_n = match coerce_to_number(&rhs) {
+        Some(n) => n,
+        None => return Err(LuaError::InvalidArithmetic(rhs)),
+    };
+
+    Ok(Value::Number(number_operation(lhs_n, rhs_n)))
+}
+
+fn coerce_to_string(value: &Value) -> Option<String> {
+    match value {
+        Value::String(s) => Some(s.clone()),
+        Value::Number(n) => Some(format_number(*n)),
+        _ => None,
+    }
+}
+
+pub fn execute_concat_operation(lhs: Value, rhs: Value) -> Result<Value> {
+    let lhs_s = match coerce_to_string(&lhs) {
+        Some(s) => s,
+        None => return Err(LuaError::InvalidConcat(lhs)),
+    };
+
+    let rhs_s = match coerce_to_string(&rhs) {
+        Some(s) => s,
+        None => return Err(LuaError::InvalidConcat(rhs)),
+    };
+
+    Ok(Value::String(lhs_s + &rhs_s))
+}
+
+pub fn execute_negate_operation(value: Value) -> Result<Value> {
+    match coerce_to_number(&value) {
+        Some(n) => Ok(Value::Number(-n)),
+        None => Err(LuaError::InvalidArithmetic(value)),
     }
 }
+
+pub fn execute_length_operation(value: Value) -> Result<Value> {
+    match value {
+        Value::String(s) => Ok(Value::Number(s.len() as f64)),
+        Value::Table(table) => Ok(Value::Number(table.borrow().length() as f64)),
+        _ => Err(LuaError::InvalidLength(value)),
+    }
+}
+
+/// Lua's raw equality, tables and functions are only equal to themselves.
+pub fn is_equal(lhs: &Value, rhs: &Value) -> bool {
+    match (lhs, rhs) {
+        (Value::Nil, Value::Nil) => true,
+        (Value::Number(a), Value::Number(b)) => a == b,
+        (Value::String(a), Value::String(b)) => a == b,
+        (Value::Boolean(a), Value::Boolean(b)) => a == b,
+        (Value::Table(a), Value::Table(b)) => Rc::ptr_eq(a, b),
+        (Value::Function(a), Value::Function(b)) => Rc::ptr_eq(a, b),
+        (Value::NativeFunction(a), Value::NativeFunction(b)) => std::ptr::fn_addr_eq(*a, *b),
+        (Value::NativeClosure(a), Value::NativeClosure(b)) => a == b,
+        _ => false,
+    }
+}
+
+pub fn execute_comparison_operation(lhs: Value,
+                                    rhs: Value,
+                                    ordering_operation: fn(Ordering) -> bool) -> Result<Value> {
+    let ordering = match (&lhs, &rhs) {
+        (Value::Number(a), Value::Number(b)) => a.partial_cmp(b),
+        (Value::String(a), Value::String(b)) => Some(a.as_bytes().cmp(b.as_bytes())),
+        _ => return Err(LuaError::InvalidComparison(lhs, rhs)),
+    };
+
+    // NOTE: Comparisons involving NaN have no ordering, so are always false.
+    Ok(Value::Boolean(ordering.is_some_and(ordering_operation)))
+}
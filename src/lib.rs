@@ -0,0 +1,8 @@
+#![allow(clippy::vec_box, clippy::borrowed_box)]
+
+use lalrpop_util::lalrpop_mod;
+
+lalrpop_mod!(#[allow(clippy::all)] pub lua_parser);
+
+pub mod ast;
+pub mod interpreter;
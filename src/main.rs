@@ -3,44 +3,66 @@ use std::fs::File;
 use std::io::Read;
 use std::error::Error;
 
-use lalrpop_util::lalrpop_mod;
-use crate::interpreter::{Interpreter, Value};
+use rust_lua::interpreter::{self, Interpreter, Value};
 
-lalrpop_mod!(pub lua_parser);
-
-mod ast;
-mod interpreter;
+#[cfg(test)]
+use rust_lua::{ast, lua_parser};
 
 #[cfg(test)]
 mod test;
 
-fn execute_script(script: &str) -> interpreter::Result<Value> {
+/// The line `print` writes for its arguments, which are converted with
+/// `tostring` and separated by tabs.
+fn print_line(interpreter: &mut Interpreter, arguments: &[Value]) -> interpreter::Result<String> {
+    let arguments = arguments.iter()
+        .map(|argument| interpreter.tostring(argument))
+        .collect::<interpreter::Result<Vec<_>>>()?;
+
+    Ok(arguments.join("\t"))
+}
+
+fn execute_script(chunk_name: &str, script: &str) -> interpreter::Result<Value> {
     let mut interpreter = Interpreter::new();
-    interpreter.define("print", |arguments| {
-        for (i, argument) in arguments.iter().enumerate() {
-            if i == arguments.len() - 1 {
-                println!("{}", argument);
-            } else {
-                print!("{} ", argument);
-            }
-        }
-        Value::Nil
+    interpreter.set_chunk_name(chunk_name);
+    interpreter.define("print", |interpreter, arguments| {
+        println!("{}", print_line(interpreter, &arguments)?);
+        Ok(Vec::new())
     });
 
-    interpreter.execute(&script)
+    interpreter.execute(script)
+}
+
+fn version() -> String {
+    format!("RustLua {}, Lua 5.4 subset", env!("CARGO_PKG_VERSION"))
 }
 
 fn main() -> Result<(), Box<dyn Error>> {
-    if args().len() < 2 {
+    // NOTE: Flags are handled instead of running any scripts, wherever they
+    //       appear in the arguments.
+    let (flags, file_paths): (Vec<_>, Vec<_>) = args().skip(1)
+        .partition(|argument| argument.starts_with("--"));
+
+    if let Some(flag) = flags.first() {
+        return match flag.as_str() {
+            "--version" => {
+                println!("{}", version());
+                Ok(())
+            },
+
+            _ => Err(Box::from(format!("Error: Unknown flag '{}'", flag))),
+        };
+    }
+
+    if file_paths.is_empty() {
         return Err(Box::from("Error: Please specify a lua script file to execute"));
     }
 
-    for file_path in args().skip(1) {
-        let mut file = File::open(file_path)?;
+    for file_path in file_paths {
+        let mut file = File::open(&file_path)?;
         let mut script = String::new();
         file.read_to_string(&mut script)?;
 
-        execute_script(&script)?;
+        execute_script(&file_path, &script)?;
     }
 
     Ok(())
@@ -1,4 +1,5 @@
-use crate::interpreter::{Interpreter, Value, LuaError, self};
+use crate::interpreter::{Interpreter, Value, LuaError, ParseError, self};
+use crate::ast::Statement;
 
 fn run_test_script(script: &str) -> interpreter::Result<Value> {
     let mut interpreter = Interpreter::new();
@@ -11,6 +12,11 @@ fn test_literal(lua: &str, expected: Value) {
     assert_eq!(x, expected);
 }
 
+/// The result of a script that gives the string `s`.
+fn string(s: &str) -> interpreter::Result<Value> {
+    Ok(Value::String(s.to_owned()))
+}
+
 #[test]
 fn test_literals() {
     // Strings
@@ -21,11 +27,24 @@ fn test_literals() {
     test_literal("21.5", Value::Number(21.5));
     test_literal(".5", Value::Number(0.5));
     test_literal("5.", Value::Number(5.));
+    test_literal("0xff", Value::Number(255.0));
+    test_literal("0X1A", Value::Number(26.0));
+    test_literal("0x1p4", Value::Number(16.0));
+    test_literal("0x.8", Value::Number(0.5));
+    test_literal("0x1p99999999999", Value::Number(f64::INFINITY));
+    test_literal("0x1p-99999999999", Value::Number(0.0));
+    test_literal("0x0p99999999999", Value::Number(0.0));
+    test_literal("1e10", Value::Number(1e10));
+    test_literal("2.5e-3", Value::Number(2.5e-3));
+    test_literal("5E+2", Value::Number(500.0));
 
     // Booleans
     test_literal("true", Value::Boolean(true));
     test_literal("false", Value::Boolean(false));
 
+    // Nil
+    test_literal("nil", Value::Nil);
+
     // Table
     test_literal("{}", Value::Table(Default::default()));
 }
@@ -99,7 +118,45 @@ fn test_captures() {
         x()
         return x()
     "#);
-    assert_eq!(x, Err(LuaError::InvalidArithmetic(Value::Nil)));
+    assert_eq!(x, Err(LuaError::UpvalueBeforeDeclaration("l".to_owned())));
+    assert_eq!(LuaError::UpvalueBeforeDeclaration("l".to_owned()).to_string(),
+        "attempt to use upvalue 'l' before it is assigned");
+
+    // A local of the same name declared after the closure, but in another
+    // function, is fine.
+    let x = run_test_script(r#"
+        function get() return l end
+        function f()
+            local l = 1
+            return l
+        end
+        l = 2
+        return get() + f()
+    "#);
+    assert_eq!(x, Ok(Value::Number(3.0)));
+
+    // Before the local is declared, the closure uses the global, which is
+    // only wrong if the global is unset.
+    assert_eq!(run_test_script("x = 10 local function f() return x end local x = 5 return f()"), Ok(Value::Number(10.0)));
+    assert_eq!(run_test_script("function f() return type end local type = 1 return f()(nil)"),
+        Ok(Value::String("nil".to_owned())));
+    assert_eq!(run_test_script("local function f() return y end local y = 1 return f()"), Ok(Value::Nil));
+    assert_eq!(run_test_script("local function f() return y.z end local y = {} return f()"),
+        Err(LuaError::UpvalueBeforeDeclaration("y".to_owned())));
+
+    // Only the closure's own uses of the name are reported, not other uses of
+    // the same global, even in later scripts.
+    let mut interpreter = Interpreter::new();
+    let x = interpreter.execute(r#"
+        do
+            local function f() return cfg end
+            local cfg = 1
+        end
+        function g() return cfg.x end
+        return select(2, pcall(g))
+    "#);
+    assert_eq!(x, Ok(Value::String("attempt to index a nil value".to_owned())));
+    assert_eq!(interpreter.execute("return cfg.x"), Err(LuaError::InvalidIndex(Value::Nil)));
 }
 
 #[test]
@@ -136,6 +193,18 @@ fn test_numeric_for() {
     ");
     assert_eq!(x, Ok(Value::Number(15.0)));
 
+    let x = run_test_script(r#"
+        x = ""
+        for i = 5, 1, -1 do
+            x = x .. i
+        end
+
+        return x
+    "#);
+    assert_eq!(x, Ok(Value::String("54321".to_owned())));
+    assert_eq!(run_test_script("x = 0 for i = 1, 5, -1 do x = x + 1 end return x"), Ok(Value::Number(0.0)));
+
+    assert_eq!(run_test_script("for i = 1, 10, 0 do end"), Err(LuaError::ForStepIsZero));
     assert_eq!(run_test_script("for i = nil, 0 do end"), Err(LuaError::BadForInitialValue(Value::Nil)));
     assert_eq!(run_test_script("for i = 0, nil do end"), Err(LuaError::BadForLimit(Value::Nil)));
     assert_eq!(run_test_script("for i = 0, 1, nil do end"), Err(LuaError::BadForStep(Value::Nil)));
@@ -148,6 +217,263 @@ fn test_logic_operations() {
     assert_eq!(run_test_script("return 1 > 2"), Ok(Value::Boolean(false)));
     assert_eq!(run_test_script("return 2 <= 2"), Ok(Value::Boolean(true)));
     assert_eq!(run_test_script("return 1 >= 2"), Ok(Value::Boolean(false)));
+    assert_eq!(run_test_script("return 2 >= 2"), Ok(Value::Boolean(true)));
+    assert_eq!(run_test_script("return 3 > 2"), Ok(Value::Boolean(true)));
+    assert_eq!(run_test_script("return -1 < 0"), Ok(Value::Boolean(true)));
+    assert_eq!(run_test_script("return 1 < 0 / 0"), Ok(Value::Boolean(false)));
+}
+
+#[test]
+fn test_string_comparison() {
+    assert_eq!(run_test_script("return \"a\" < \"b\""), Ok(Value::Boolean(true)));
+    assert_eq!(run_test_script("return \"abc\" < \"abd\""), Ok(Value::Boolean(true)));
+    assert_eq!(run_test_script("return \"ab\" < \"abc\""), Ok(Value::Boolean(true)));
+    assert_eq!(run_test_script("return \"Z\" > \"a\""), Ok(Value::Boolean(false)));
+    assert_eq!(run_test_script("return \"b\" >= \"b\""), Ok(Value::Boolean(true)));
+    assert_eq!(run_test_script("return \"b\" <= \"a\""), Ok(Value::Boolean(false)));
+
+    // Strings are compared by their bytes, not numerically.
+    assert_eq!(run_test_script("return \"10\" < \"9\""), Ok(Value::Boolean(true)));
+    assert_eq!(run_test_script("return \"z\" < \"\u{e9}\""), Ok(Value::Boolean(true)));
+}
+
+#[test]
+fn test_comparison_error() {
+    assert_eq!(run_test_script("return 1 < \"2\""),
+        Err(LuaError::InvalidComparison(Value::Number(1.0), Value::String("2".to_owned()))));
+    assert_eq!(run_test_script("return {} < {}"),
+        Err(LuaError::InvalidComparison(Value::Table(Default::default()), Value::Table(Default::default()))));
+
+    let error = run_test_script("return 1 < \"2\"").unwrap_err();
+    assert_eq!(error.to_string(), "attempt to compare number with string");
+
+    // Every ordering operator rejects mismatched operands rather than returning nil.
+    for operator in ["<", ">", "<=", ">="] {
+        assert_eq!(run_test_script(&format!("return \"1\" {} 1", operator)),
+            Err(LuaError::InvalidComparison(Value::String("1".to_owned()), Value::Number(1.0))));
+        assert_eq!(run_test_script(&format!("return true {} false", operator)),
+            Err(LuaError::InvalidComparison(Value::Boolean(true), Value::Boolean(false))));
+        assert_eq!(run_test_script(&format!("return x {} 1", operator)),
+            Err(LuaError::InvalidComparison(Value::Nil, Value::Number(1.0))));
+    }
+}
+
+#[test]
+fn test_equals() {
+    assert_eq!(run_test_script("return \"a\" == \"a\""), Ok(Value::Boolean(true)));
+    assert_eq!(run_test_script("return \"a\" == \"b\""), Ok(Value::Boolean(false)));
+    assert_eq!(run_test_script("return true == true"), Ok(Value::Boolean(true)));
+    assert_eq!(run_test_script("return true == false"), Ok(Value::Boolean(false)));
+    assert_eq!(run_test_script("return x == y"), Ok(Value::Boolean(true)));
+
+    // Values of different types are never equal, with no coercion.
+    assert_eq!(run_test_script("return 1 == \"1\""), Ok(Value::Boolean(false)));
+    assert_eq!(run_test_script("return false == x"), Ok(Value::Boolean(false)));
+    assert_eq!(run_test_script("return 0 == false"), Ok(Value::Boolean(false)));
+
+    // Tables and functions are compared by identity.
+    assert_eq!(run_test_script("return {} == {}"), Ok(Value::Boolean(false)));
+    assert_eq!(run_test_script("t = {} u = t return t == u"), Ok(Value::Boolean(true)));
+    assert_eq!(run_test_script("f = function() end return f == f"), Ok(Value::Boolean(true)));
+    assert_eq!(run_test_script("return function() end == function() end"), Ok(Value::Boolean(false)));
+}
+
+#[test]
+fn test_not_equals() {
+    assert_eq!(run_test_script("return 1 ~= 2"), Ok(Value::Boolean(true)));
+    assert_eq!(run_test_script("return 1 ~= 1"), Ok(Value::Boolean(false)));
+    assert_eq!(run_test_script("return \"a\" ~= \"b\""), Ok(Value::Boolean(true)));
+    assert_eq!(run_test_script("return \"a\" ~= \"a\""), Ok(Value::Boolean(false)));
+    assert_eq!(run_test_script("return x ~= y"), Ok(Value::Boolean(false)));
+    assert_eq!(run_test_script("return x ~= 1"), Ok(Value::Boolean(true)));
+    assert_eq!(run_test_script("return 1 ~= \"1\""), Ok(Value::Boolean(true)));
+    assert_eq!(run_test_script("return {} ~= {}"), Ok(Value::Boolean(true)));
+    assert_eq!(run_test_script("t = {} return t ~= t"), Ok(Value::Boolean(false)));
+    assert_eq!(run_test_script("return true ~= false"), Ok(Value::Boolean(true)));
+    assert_eq!(run_test_script("return false ~= false"), Ok(Value::Boolean(false)));
+    assert_eq!(run_test_script("return false ~= x"), Ok(Value::Boolean(true)));
+
+    // `~=` is always the inverse of `==`.
+    for (a, b) in [("1", "1"), ("1", "2"), ("\"a\"", "\"a\""), ("true", "1"), ("x", "false")] {
+        let equals = run_test_script(&format!("return {} == {}", a, b));
+        let not_equals = run_test_script(&format!("return {} ~= {}", a, b));
+        assert_ne!(equals, not_equals);
+    }
+}
+
+#[test]
+fn test_modulo() {
+    assert_eq!(run_test_script("return 5 % 3"), Ok(Value::Number(2.0)));
+    assert_eq!(run_test_script("return 5.5 % 2"), Ok(Value::Number(1.5)));
+    assert_eq!(run_test_script("return 1 + 5 % 3"), Ok(Value::Number(3.0)));
+
+    // Negative operands follow the sign of the divisor, unlike Rust's `%`.
+    assert_eq!(run_test_script("return -5 % 3"), Ok(Value::Number(1.0)));
+    assert_eq!(run_test_script("return 5 % -3"), Ok(Value::Number(-1.0)));
+    assert_eq!(run_test_script("return -1 % 3"), Ok(Value::Number(2.0)));
+
+    // Numeric strings are coerced, others are an error.
+    assert_eq!(run_test_script("return \"7\" % 3"), Ok(Value::Number(1.0)));
+    assert_eq!(run_test_script("return 7 % \"x\""), Err(LuaError::InvalidArithmetic(Value::String("x".to_owned()))));
+}
+
+#[test]
+fn test_negate() {
+    assert_eq!(run_test_script("return -(3 + 2)"), Ok(Value::Number(-5.0)));
+    assert_eq!(run_test_script("return - -2"), Ok(Value::Number(2.0)));
+    assert_eq!(run_test_script("return 1 - -2"), Ok(Value::Number(3.0)));
+    assert_eq!(run_test_script("return -2 * 3"), Ok(Value::Number(-6.0)));
+    assert_eq!(run_test_script("x = 5 return -x"), Ok(Value::Number(-5.0)));
+    assert_eq!(run_test_script("return -true"), Err(LuaError::InvalidArithmetic(Value::Boolean(true))));
+}
+
+#[test]
+fn test_power() {
+    assert_eq!(run_test_script("return 2 ^ 3"), Ok(Value::Number(8.0)));
+    assert_eq!(run_test_script("return 2 ^ 2 ^ 3"), Ok(Value::Number(256.0)));
+    assert_eq!(run_test_script("return -2 ^ 2"), Ok(Value::Number(-4.0)));
+    assert_eq!(run_test_script("return (-2) ^ 2"), Ok(Value::Number(4.0)));
+    assert_eq!(run_test_script("return 2 ^ -1"), Ok(Value::Number(0.5)));
+    assert_eq!(run_test_script("return 2 * 3 ^ 2"), Ok(Value::Number(18.0)));
+    assert_eq!(run_test_script("return 2 ^ 10"), Ok(Value::Number(1024.0)));
+
+    match run_test_script("return (-1) ^ 0.5") {
+        Ok(Value::Number(n)) => assert!(n.is_nan()),
+        result => panic!("Expected NaN, got {:?}", result),
+    }
+}
+
+#[test]
+fn test_concat() {
+    assert_eq!(run_test_script("return \"a\" .. \"b\" .. \"c\""), Ok(Value::String("abc".to_owned())));
+    assert_eq!(run_test_script("return \"x = \" .. 5"), Ok(Value::String("x = 5".to_owned())));
+    assert_eq!(run_test_script("return 1 .. 2"), Ok(Value::String("12".to_owned())));
+    assert_eq!(run_test_script("return \"n\" .. 1 + 2"), Ok(Value::String("n3".to_owned())));
+    assert_eq!(run_test_script("return \"a\" .. {}"), Err(LuaError::InvalidConcat(Value::Table(Default::default()))));
+    assert_eq!(run_test_script("return true .. \"a\""), Err(LuaError::InvalidConcat(Value::Boolean(true))));
+    assert_eq!(run_test_script("return nil .. \"a\""), Err(LuaError::InvalidConcat(Value::Nil)));
+}
+
+#[test]
+fn test_concat_number_format() {
+    assert_eq!(run_test_script("return 42 .. \" bottles\""), string("42 bottles"));
+
+    // Numbers are formatted like `%.14g`.
+    for (number, expected) in [
+        ("1.5", "1.5"), ("-0.25", "-0.25"), ("1 / 3", "0.33333333333333"), ("100 / 3", "33.333333333333"),
+        ("10 ^ 13", "10000000000000"), ("10 ^ 14", "1e+14"), ("2 ^ 53", "9.007199254741e+15"),
+        ("0.0001", "0.0001"), ("0.00001", "1e-05"), ("1 / 0", "inf"), ("-1 / 0", "-inf"),
+    ] {
+        assert_eq!(run_test_script(&format!("return ({}) .. \"\"", number)), string(expected), "{}", number);
+    }
+}
+
+#[test]
+fn test_length() {
+    assert_eq!(run_test_script("return #\"hello\""), Ok(Value::Number(5.0)));
+    assert_eq!(run_test_script("return #\"\""), Ok(Value::Number(0.0)));
+    assert_eq!(run_test_script("return #{1, 2, 3}"), Ok(Value::Number(3.0)));
+    assert_eq!(run_test_script("return #{}"), Ok(Value::Number(0.0)));
+    assert_eq!(run_test_script("return #{x = 1, 2}"), Ok(Value::Number(1.0)));
+    assert_eq!(run_test_script("t = {1, 2, 3} t[2] = nil return #t"), Ok(Value::Number(1.0)));
+    assert_eq!(run_test_script("return -#\"abc\" + 1"), Ok(Value::Number(-2.0)));
+    assert_eq!(run_test_script("return #true"), Err(LuaError::InvalidLength(Value::Boolean(true))));
+}
+
+#[test]
+fn test_length_precedence() {
+    assert_eq!(run_test_script("t = {x = {1, 2}} return #t.x"), Ok(Value::Number(2.0)));
+    assert_eq!(run_test_script("t = {\"abcd\"} return #t[1]"), Ok(Value::Number(4.0)));
+    assert_eq!(run_test_script("return #{1, 2, 3} + 1"), Ok(Value::Number(4.0)));
+    assert_eq!(run_test_script("return #{1, 2, 3} == 3"), Ok(Value::Boolean(true)));
+    assert_eq!(run_test_script("return (#\"ab\") ^ 2"), Ok(Value::Number(4.0)));
+
+    // Exponentiation binds tighter than the unary operators.
+    assert_eq!(run_test_script("return #\"ab\" ^ 2"), Err(LuaError::InvalidArithmetic(Value::String("ab".to_owned()))));
+    assert_eq!(run_test_script("return ##\"abc\""), Err(LuaError::InvalidLength(Value::Number(3.0))));
+}
+
+#[test]
+fn test_length_cache() {
+    let x = run_test_script(r"
+        t = {}
+        for i = 1, 1000 do
+            t[i] = i
+        end
+
+        total = 0
+        for i = 1, 1000 do
+            total = total + #t
+        end
+
+        return total
+    ");
+    assert_eq!(x, Ok(Value::Number(1000000.0)));
+
+    let x = run_test_script(r"
+        t = {1, 2, 3}
+        a = #t
+        t[4] = 4
+        b = #t
+        t[2] = nil
+        c = #t
+        t[2] = 2
+        t.x = 1
+        return a .. b .. c .. #t
+    ");
+    assert_eq!(x, Ok(Value::String("3414".to_owned())));
+}
+
+#[test]
+fn test_parentheses() {
+    assert_eq!(run_test_script("return (1 + 2) * 3"), Ok(Value::Number(9.0)));
+    assert_eq!(run_test_script("x = {y = 2} return (x).y"), Ok(Value::Number(2.0)));
+}
+
+#[test]
+fn test_operator_precedence() {
+    assert_eq!(run_test_script("return 1 + 2 * 3"), Ok(Value::Number(7.0)));
+    assert_eq!(run_test_script("return 1 + 2 == 3"), Ok(Value::Boolean(true)));
+    assert_eq!(run_test_script("return 1 == 1 and 2 == 3"), Ok(Value::Boolean(false)));
+}
+
+#[test]
+fn test_and_or() {
+    assert_eq!(run_test_script("return 1 and 2"), Ok(Value::Number(2.0)));
+    assert_eq!(run_test_script("return nil and 2"), Ok(Value::Nil));
+    assert_eq!(run_test_script("return false or 2"), Ok(Value::Number(2.0)));
+    assert_eq!(run_test_script("return 1 or 2"), Ok(Value::Number(1.0)));
+
+    // The right hand side must not be evaluated when short-circuiting.
+    assert_eq!(run_test_script("return false and true()"), Ok(Value::Boolean(false)));
+    assert_eq!(run_test_script("return 1 or true()"), Ok(Value::Number(1.0)));
+}
+
+#[test]
+fn test_conditional_expression() {
+    assert_eq!(run_test_script("return true and 1 or 2"), Ok(Value::Number(1.0)));
+    assert_eq!(run_test_script("return false and 1 or 2"), Ok(Value::Number(2.0)));
+
+    // The well known pitfall, a falsy middle operand falls through to the last.
+    assert_eq!(run_test_script("return true and false or 2"), Ok(Value::Number(2.0)));
+}
+
+#[test]
+fn test_large_numeric_index() {
+    let x = run_test_script(r"
+        t = {}
+        t[3000000000] = 1
+        t[2147483647] = 2
+        return t[3000000000]
+    ");
+    assert_eq!(x, Ok(Value::Number(1.0)));
+
+    let x = run_test_script(r"
+        t = {}
+        t[3000000000] = 1
+        return t[2147483647]
+    ");
+    assert_eq!(x, Ok(Value::Nil));
 }
 
 #[test]
@@ -172,4 +498,2075 @@ fn test_comment() {
         return 21 -- more commentary
     ");
     assert_eq!(x, Ok(Value::Number(21.0)));
+
+    // A comment may end the file without a newline.
+    let x = run_test_script("-- Setup\n-- x = 1\nx = 2\n-- Result\nreturn x -- done");
+    assert_eq!(x, Ok(Value::Number(2.0)));
+
+    // Block comments can span lines, and end at the first closing bracket of
+    // the same level.
+    let x = run_test_script(r"
+        x = 1
+        --[[ x = 2
+        x = 3 ]] x = x + 1
+        --[==[
+            x = 10 ]] ]=]
+        ]==]
+        --[=[ ]=] x = x * 2
+        --[ not a block comment
+        return x
+    ");
+    assert_eq!(x, Ok(Value::Number(4.0)));
+
+    // Dashes within strings are not a comment.
+    let x = run_test_script("return \"a -- b\" -- c");
+    assert_eq!(x, Ok(Value::String("a -- b".to_owned())));
+
+    // Block comments may be of any level, and don't change the lines of any
+    // errors after them.
+    let x = run_test_script(r#"
+        x = 1 --[=====[ x = 2 ]====] "
+        ]=====] return x .. [[ --[==[ ]] .. "--[["
+    "#);
+    assert_eq!(x, Ok(Value::String("1 --[==[ --[[".to_owned())));
+    match run_test_script("--[====[\n\n]====] x = = 1") {
+        Err(LuaError::ParseError(ParseError { line, column, .. })) => assert_eq!((line, column), (3, 12)),
+        result => panic!("Expected a parse error, got {:?}", result),
+    }
+
+    match run_test_script("x = 1\n--[==[ ]=]") {
+        Err(LuaError::ParseError(ParseError { line, message, .. })) => {
+            assert_eq!(line, 2);
+            assert_eq!(message, "unfinished long comment");
+        },
+        result => panic!("Expected a parse error, got {:?}", result),
+    }
+}
+
+#[test]
+fn test_parse_error() {
+    match run_test_script("x = = 1") {
+        Err(LuaError::ParseError(ParseError { line, column, .. })) => assert_eq!((line, column), (1, 5)),
+        result => panic!("Expected a parse error, got {:?}", result),
+    }
+
+    match run_test_script("x = 1\n  return )") {
+        Err(LuaError::ParseError(ParseError { line, column, message })) => {
+            assert_eq!((line, column), (2, 10));
+            assert!(message.starts_with("unexpected ')'"));
+        },
+        result => panic!("Expected a parse error, got {:?}", result),
+    }
+
+    match run_test_script("if true then") {
+        Err(LuaError::ParseError(ParseError { message, .. })) => assert!(message.contains("\"end\"")),
+        result => panic!("Expected a parse error, got {:?}", result),
+    }
+}
+
+#[test]
+fn test_local_attributes() {
+    assert_eq!(run_test_script("local x <const> = 5 return x"), Ok(Value::Number(5.0)));
+    assert_eq!(run_test_script("local x <close> = false return x"), Ok(Value::Boolean(false)));
+
+    assert_eq!(run_test_script("local x <const> = 5 x = 6"), Err(LuaError::AssignToConst("x".to_owned())));
+    assert_eq!(run_test_script("local x <close> = y x = 6"), Err(LuaError::AssignToConst("x".to_owned())));
+    assert_eq!(run_test_script("local x <close> = {}"), Err(LuaError::NonClosableValue("x".to_owned())));
+
+    // Assigning to a const is caught before anything runs, including from a closure.
+    let x = run_test_script(r"
+        local x <const> = 1
+        true()
+        function f()
+            x = 2
+        end
+    ");
+    assert_eq!(x, Err(LuaError::AssignToConst("x".to_owned())));
+
+    // Shadowing a const with a new local is allowed, as is a const in a block that has ended.
+    assert_eq!(run_test_script("local x <const> = 1 local x = 2 x = 3 return x"), Ok(Value::Number(3.0)));
+    assert_eq!(run_test_script("if true then local x <const> = 1 end x = 2 return x"), Ok(Value::Number(2.0)));
+
+    match run_test_script("local x <static> = 1") {
+        Err(LuaError::ParseError(ParseError { line, column, message })) => {
+            assert_eq!((line, column, message.as_str()), (1, 10, "unknown attribute"));
+        },
+        result => panic!("Expected a parse error, got {:?}", result),
+    }
+}
+
+#[test]
+fn test_multiple_assignment() {
+    assert_eq!(run_test_script("a, b = 1, 2 return a + b"), Ok(Value::Number(3.0)));
+    assert_eq!(run_test_script("a, b = 1, 2 a, b = b, a return a .. b"), Ok(Value::String("21".to_owned())));
+    assert_eq!(run_test_script("a, b = 1 return b"), Ok(Value::Nil));
+    assert_eq!(run_test_script("a = 1, 2 return a"), Ok(Value::Number(1.0)));
+    assert_eq!(run_test_script("t = {} t.x, t[1] = 1, 2 return t.x + t[1]"), Ok(Value::Number(3.0)));
+
+    // Targets and values are evaluated before any assignments happen.
+    assert_eq!(run_test_script("i = 1 t = {} i, t[i] = i + 1, 20 return t[1] .. i"), Ok(Value::String("202".to_owned())));
+    assert_eq!(run_test_script("t = {} t, t.x = 1, 2 return t"), Ok(Value::Number(1.0)));
+
+    // A call as the last value gives all of its results, anywhere else only its first.
+    let f = "function f() return 1, 2, 3 end";
+    assert_eq!(run_test_script(&format!("{} a, b, c = f() return a + b + c", f)), Ok(Value::Number(6.0)));
+    assert_eq!(run_test_script(&format!("{} a, b, c = f(), 10 return c", f)), Ok(Value::Nil));
+    assert_eq!(run_test_script(&format!("{} a, b, c = f(), 10 return a + b", f)), Ok(Value::Number(11.0)));
+    assert_eq!(run_test_script(&format!("{} a, b = (f()) return b", f)), Ok(Value::Nil));
+
+    // Only variables and table fields can be assigned to.
+    assert!(matches!(run_test_script("f() = 1"), Err(LuaError::ParseError(_))));
+    assert!(matches!(run_test_script("a, f() = 1, 2"), Err(LuaError::ParseError(_))));
+}
+
+#[test]
+fn test_global_table() {
+    assert_eq!(run_test_script("x = 1 return _G.x"), Ok(Value::Number(1.0)));
+    assert_eq!(run_test_script("_G.x = 2 return x"), Ok(Value::Number(2.0)));
+    assert_eq!(run_test_script("return _G._G == _G"), Ok(Value::Boolean(true)));
+}
+
+#[test]
+fn test_strict_globals() {
+    let script = r"
+        function f()
+            x = 1
+            return x
+        end
+
+        return f()
+    ";
+
+    // Permissive by default.
+    assert_eq!(run_test_script(script), Ok(Value::Number(1.0)));
+
+    let mut interpreter = Interpreter::new();
+    interpreter.set_strict_globals(true);
+    assert_eq!(interpreter.execute(script), Err(LuaError::Custom("assignment to undeclared global 'x'".to_owned())));
+
+    // Locals, and globals declared through `_G`, can still be assigned to.
+    assert_eq!(interpreter.execute("local y = 1 y = 2 return y"), Ok(Value::Number(2.0)));
+    assert_eq!(interpreter.execute("_G.x = 1 x = 2 return x"), Ok(Value::Number(2.0)));
+}
+
+#[test]
+fn test_string_format_char_and_string() {
+    assert_eq!(run_test_script("return string.format(\"%c\", 65)"), string("A"));
+    assert_eq!(run_test_script("return string.format(\"%c%c\", 72, \"105\")"), string("Hi"));
+    assert_eq!(run_test_script("return string.format(\"%s\", 42)"), string("42"));
+    assert_eq!(run_test_script("return string.format(\"%s %s %s\", \"a\", true, nil)"), string("a true nil"));
+    assert_eq!(run_test_script("return string.format(\"[%5s][%-5s][%.2s]\", \"ab\", \"ab\", \"abc\")"), string("[   ab][ab   ][ab]"));
+    assert_eq!(run_test_script("return string.format(\"100%%\")"), string("100%"));
+
+    assert_eq!(run_test_script("return string.format(\"%c\", {})"),
+        Err(LuaError::BadArgument(2, "format", "number expected, got table".to_owned())));
+    assert_eq!(run_test_script("return string.format(\"%c\", 256)"),
+        Err(LuaError::BadArgument(2, "format", "value out of range".to_owned())));
+    assert_eq!(run_test_script("return string.format(\"%c\", 200)"),
+        Err(LuaError::BadArgument(2, "format", "invalid UTF-8".to_owned())));
+    assert_eq!(run_test_script("return string.format(\"%y\", 1)"),
+        Err(LuaError::Custom("invalid conversion '%y' to 'format'".to_owned())));
+
+    // Widths and precisions are at most two digits.
+    assert_eq!(run_test_script("return string.format(\"%99d|%.99s\", 1, \"a\")"), string(&format!("{:>99}|a", 1)));
+    assert_eq!(run_test_script("return string.format(\"%100d\", 1)"),
+        Err(LuaError::Custom("invalid conversion '%100d' to 'format'".to_owned())));
+    assert_eq!(run_test_script("return string.format(\"%-5.100f\", 1)"),
+        Err(LuaError::Custom("invalid conversion '%-5.100f' to 'format'".to_owned())));
+}
+
+#[test]
+fn test_varargs() {
+    // Forwarding
+    let x = run_test_script(r#"
+        function sum(a, b, c)
+            return a + b + c
+        end
+
+        function forward(...)
+            return sum(...)
+        end
+
+        return forward(1, 2, 3)
+    "#);
+    assert_eq!(x, Ok(Value::Number(6.0)));
+
+    // Counting
+    let count = r##"
+        function count(...)
+            return select("#", ...)
+        end
+    "##;
+    assert_eq!(run_test_script(&format!("{} return count()", count)), Ok(Value::Number(0.0)));
+    assert_eq!(run_test_script(&format!("{} return count(1, nil, 3)", count)), Ok(Value::Number(3.0)));
+    assert_eq!(run_test_script(&format!("{} return count(1, count(2, 3))", count)), Ok(Value::Number(2.0)));
+
+    // Fixed parameters come first, and only the last expression expands
+    let x = run_test_script(r#"
+        function f(first, ...)
+            local t = {..., ...}
+            return first .. #t
+        end
+
+        return f("a", 1, 2, 3)
+    "#);
+    assert_eq!(x, Ok(Value::String("a4".to_owned())));
+
+    // Parentheses truncate to a single value
+    assert_eq!(run_test_script("function f(...) return select(\"#\", (...)) end return f(1, 2)"), Ok(Value::Number(1.0)));
+
+    // Tails
+    assert_eq!(run_test_script("return select(2, \"a\", \"b\", \"c\")"), Ok(Value::String("b".to_owned())));
+    assert_eq!(run_test_script("return select(-1, \"a\", \"b\", \"c\")"), Ok(Value::String("c".to_owned())));
+    assert_eq!(run_test_script("return select(\"#\", select(2, \"a\", \"b\", \"c\"))"), Ok(Value::Number(2.0)));
+    assert_eq!(run_test_script("return select(0, \"a\")"),
+        Err(LuaError::BadArgument(1, "select", "index out of range".to_owned())));
+}
+
+#[test]
+fn test_argument_count_mismatch() {
+    let script = r#"
+        function f(a, b)
+            if b then return a + b end
+            return a
+        end
+    "#;
+    assert_eq!(run_test_script(&format!("{} return f(1)", script)), Ok(Value::Number(1.0)));
+    assert_eq!(run_test_script(&format!("{} return f(1, 2, 3)", script)), Ok(Value::Number(3.0)));
+}
+
+#[test]
+fn test_table_clone_shares_contents() {
+    let mut interpreter = Interpreter::new();
+    let table = interpreter.execute("t = { x = 1 } return t").expect("No errors");
+    let clone = table.clone();
+
+    // Mutating through one handle is visible through the other.
+    interpreter.execute("t.x = 2").expect("No errors");
+    let expected = interpreter.execute("return { x = 2 }").expect("No errors");
+    assert_eq!(table, expected);
+    assert_eq!(clone, expected);
+
+    match (&table, &clone) {
+        (Value::Table(a), Value::Table(b)) => assert!(std::rc::Rc::ptr_eq(a, b)),
+        _ => panic!("Expected tables"),
+    }
+
+    // The same holds for copies made by assignment within Lua.
+    let x = run_test_script(r#"
+        local a = {}
+        local b = a
+        b.x = 1
+        return a.x
+    "#);
+    assert_eq!(x, Ok(Value::Number(1.0)));
+}
+
+#[test]
+fn test_number_clone_is_independent() {
+    let mut value = Value::Number(1.0);
+    let clone = value.clone();
+    if let Value::Number(n) = &mut value {
+        *n += 1.0;
+    }
+    assert_eq!(value, Value::Number(2.0));
+    assert_eq!(clone, Value::Number(1.0));
+
+    let x = run_test_script(r#"
+        local a = 1
+        local b = a
+        b = 2
+        return a
+    "#);
+    assert_eq!(x, Ok(Value::Number(1.0)));
+}
+
+#[test]
+fn test_closure_clone_shares_upvalues() {
+    let x = run_test_script(r#"
+        function counter()
+            local n = 0
+            return function()
+                n = n + 1
+                return n
+            end
+        end
+
+        local a = counter()
+        local b = a
+        a()
+        b()
+        return a()
+    "#);
+    assert_eq!(x, Ok(Value::Number(3.0)));
+
+    // Separately created closures get their own upvalues.
+    let x = run_test_script(r#"
+        function counter()
+            local n = 0
+            return function()
+                n = n + 1
+                return n
+            end
+        end
+
+        local a = counter()
+        local b = counter()
+        a()
+        a()
+        return b()
+    "#);
+    assert_eq!(x, Ok(Value::Number(1.0)));
+}
+
+#[test]
+fn test_method_call() {
+    let x = run_test_script(r#"
+        counter = { count = 0 }
+        counter.add = function(self, n)
+            self.count = self.count + n
+            return self.count
+        end
+
+        counter:add(2)
+        return counter:add(3)
+    "#);
+    assert_eq!(x, Ok(Value::Number(5.0)));
+
+    // The receiver is only evaluated once.
+    let x = run_test_script(r#"
+        calls = 0
+        object = { get = function(self) return self end }
+        function make()
+            calls = calls + 1
+            return object
+        end
+
+        make():get()
+        return calls
+    "#);
+    assert_eq!(x, Ok(Value::Number(1.0)));
+
+    assert_eq!(run_test_script("x = 1 return x:method()"), Err(LuaError::InvalidIndex(Value::Number(1.0))));
+    assert_eq!(run_test_script("t = {} return t:method()"), Err(LuaError::InvalidCall(Value::Nil)));
+}
+
+#[test]
+fn test_string_arithmetic_coercion() {
+    assert_eq!(run_test_script("return \"10\" + 5"), Ok(Value::Number(15.0)));
+    assert_eq!(run_test_script("return \"3\" * \"4\""), Ok(Value::Number(12.0)));
+    assert_eq!(run_test_script("return \" 2.5 \" - 1"), Ok(Value::Number(1.5)));
+    assert_eq!(run_test_script("return \"1e2\" / 4"), Ok(Value::Number(25.0)));
+    assert_eq!(run_test_script("return \"0xff\" + 0"), Ok(Value::Number(255.0)));
+    assert_eq!(run_test_script("return \"-0X10\" + 0"), Ok(Value::Number(-16.0)));
+    assert_eq!(run_test_script("return -\"2\""), Ok(Value::Number(-2.0)));
+    assert_eq!(run_test_script("return -\" 0x10 \""), Ok(Value::Number(-16.0)));
+
+    for s in ["abc", "", "0x", "inf", "nan", "1 2"] {
+        assert_eq!(run_test_script(&format!("return \"{}\" + 1", s)),
+            Err(LuaError::InvalidArithmetic(Value::String(s.to_owned()))));
+        assert_eq!(run_test_script(&format!("return -\"{}\"", s)),
+            Err(LuaError::InvalidArithmetic(Value::String(s.to_owned()))));
+    }
+}
+
+#[test]
+fn test_table_constructor_ordering() {
+    // Positional items take precedence over explicit keys at the same index,
+    // wherever they appear in the constructor.
+    assert_eq!(run_test_script("t = {[1] = \"a\", \"b\"} return t[1]"), Ok(Value::String("b".to_owned())));
+    assert_eq!(run_test_script("t = {\"a\", [1] = \"b\"} return t[1]"), Ok(Value::String("a".to_owned())));
+    assert_eq!(run_test_script("t = {[2] = \"x\", \"a\", \"b\"} return t[2]"), Ok(Value::String("b".to_owned())));
+
+    // Explicit keys past the positional items are kept.
+    assert_eq!(run_test_script("t = {\"a\", [3] = \"c\", \"b\"} return #t .. t[3]"), Ok(Value::String("3c".to_owned())));
+
+    // Items are evaluated from left to right, keys before their value.
+    let x = run_test_script(r#"
+        order = ""
+        function log(s)
+            order = order .. s
+            return s
+        end
+
+        t = {log("a"), [log("b")] = log("c"), log("d")}
+        return order
+    "#);
+    assert_eq!(x, Ok(Value::String("abcd".to_owned())));
+}
+
+#[test]
+fn test_method_definition() {
+    let x = run_test_script(r#"
+        obj = { x = 5 }
+        function obj:get()
+            return self.x
+        end
+
+        function obj:add(n)
+            self.x = self.x + n
+        end
+
+        obj:add(2)
+        return obj:get()
+    "#);
+    assert_eq!(x, Ok(Value::Number(7.0)));
+
+    // Functions can also be declared as fields through a path of tables.
+    let x = run_test_script(r#"
+        local a = { b = {} }
+        function a.b.f(n)
+            return n * 2
+        end
+
+        return a.b.f(4)
+    "#);
+    assert_eq!(x, Ok(Value::Number(8.0)));
+
+    // A method declared with `:` can be called with `.`, passing `self`.
+    let x = run_test_script(r#"
+        counter = { n = 0 }
+        function counter:inc()
+            self.n = self.n + 1
+        end
+
+        counter:inc()
+        counter.inc(counter)
+        return counter.n
+    "#);
+    assert_eq!(x, Ok(Value::Number(2.0)));
+
+    assert_eq!(run_test_script("function missing:get() end"), Err(LuaError::InvalidIndex(Value::Nil)));
+}
+
+#[test]
+fn test_pcall() {
+    assert_eq!(run_test_script("return pcall(function() return 1 end)"), Ok(Value::Boolean(true)));
+    assert_eq!(run_test_script("return select(2, pcall(function(a, b) return a + b end, 1, 2))"), Ok(Value::Number(3.0)));
+
+    assert_eq!(run_test_script("return pcall(function() return 1 + {} end)"), Ok(Value::Boolean(false)));
+    assert_eq!(run_test_script("return select(2, pcall(function() return 1 + {} end))"),
+        Ok(Value::String("attempt to perform arithmetic on a table value".to_owned())));
+    assert_eq!(run_test_script("return select(2, pcall(1))"),
+        Ok(Value::String(LuaError::InvalidCall(Value::Number(1.0)).to_string())));
+
+    // Errors from calls nested inside the protected one are caught too, and
+    // the script carries on afterwards.
+    let x = run_test_script(r#"
+        local function inner() error("deep") end
+        local function outer() inner() return "unreachable" end
+
+        local ok, message = pcall(outer)
+        return tostring(ok) .. " " .. message .. " " .. select(2, pcall(outer, 1, 2))
+    "#);
+    assert_eq!(x, Ok(Value::String("false chunk:2: deep chunk:2: deep".to_owned())));
+
+    // Every result follows `true`, and a protected call can itself be protected.
+    assert_eq!(run_test_script("return select(\"#\", pcall(function() return 1, nil, 3 end))"), Ok(Value::Number(4.0)));
+    let x = run_test_script(r#"
+        local ok, inner_ok, message = pcall(pcall, error, "inner")
+        return tostring(ok) .. " " .. tostring(inner_ok) .. " " .. message
+    "#);
+    assert_eq!(x, Ok(Value::String("true false inner".to_owned())));
+}
+
+#[test]
+#[cfg(feature = "catch-panics")]
+fn test_pcall_catches_panics() {
+    let mut interpreter = Interpreter::new();
+    interpreter.define("explode", |_, _| panic!("boom"));
+
+    assert_eq!(interpreter.execute("return select(2, pcall(explode))"),
+        Ok(Value::String("internal error: boom".to_owned())));
+
+    // Outside of `pcall` the panic is still reported as an error.
+    assert_eq!(interpreter.execute("explode()"), Err(LuaError::Custom("internal error: boom".to_owned())));
+}
+
+#[test]
+fn test_dotted_function_name() {
+    assert_eq!(run_test_script("t = {} function t.f() return 1 end return t.f()"), Ok(Value::Number(1.0)));
+    assert_eq!(run_test_script("t = { x = 1 } function t.x.f() end"), Err(LuaError::InvalidIndex(Value::Number(1.0))));
+    assert_eq!(run_test_script("t = {} function t.x.f() end"), Err(LuaError::InvalidIndex(Value::Nil)));
+}
+
+#[test]
+fn test_math_floor_and_ceil() {
+    assert_eq!(run_test_script("return math.floor(3.9)"), Ok(Value::Number(3.0)));
+    assert_eq!(run_test_script("return math.floor(-3.1)"), Ok(Value::Number(-4.0)));
+    assert_eq!(run_test_script("return math.ceil(3.1)"), Ok(Value::Number(4.0)));
+    assert_eq!(run_test_script("return math.ceil(-3.9)"), Ok(Value::Number(-3.0)));
+
+    // Numeric strings are coerced.
+    assert_eq!(run_test_script("return math.floor(\"3.9\") == 3"), Ok(Value::Boolean(true)));
+    assert_eq!(run_test_script("return math.ceil(\" 0x10 \")"), Ok(Value::Number(16.0)));
+
+    assert_eq!(run_test_script("return math.floor(\"x\")"),
+        Err(LuaError::BadArgument(1, "floor", "number expected, got string".to_owned())));
+    assert_eq!(run_test_script("return math.ceil()"),
+        Err(LuaError::BadArgument(1, "ceil", "number expected, got no value".to_owned())));
+}
+
+#[test]
+fn test_nil() {
+    assert_eq!(run_test_script("local x = nil return x"), Ok(Value::Nil));
+    assert_eq!(run_test_script("function f(a) return a == nil end return f(nil)"), Ok(Value::Boolean(true)));
+    assert_eq!(run_test_script("return select(\"#\", nil, nil)"), Ok(Value::Number(2.0)));
+
+    // `nil` is reserved, so can't be assigned to or used as a name.
+    assert!(matches!(run_test_script("nil = 1"), Err(LuaError::ParseError(_))));
+    assert!(matches!(run_test_script("local nil = 1"), Err(LuaError::ParseError(_))));
+
+    // But names starting with it are still identifiers.
+    assert_eq!(run_test_script("nilly = 1 return nilly"), Ok(Value::Number(1.0)));
+}
+
+#[test]
+fn test_for_parsing() {
+    let parse = |source: &str| crate::lua_parser::ProgramParser::new()
+        .parse(&std::cell::Cell::new(200), &[0], &[], source)
+        .expect("No errors");
+
+    let program = parse("for i = 1, 3 do end");
+    assert!(matches!(program.as_slice(), [Statement::NumericFor(name, _, _, None, _)] if name == "i"));
+
+    let program = parse("for k, v in pairs(t) do end");
+    assert!(matches!(program.as_slice(), [Statement::GenericFor(names, values, _)]
+        if names == &["k", "v"] && values.len() == 1));
+
+    let program = parse("for k in next, t, nil do end");
+    assert!(matches!(program.as_slice(), [Statement::GenericFor(names, values, _)]
+        if names == &["k"] && values.len() == 3));
+}
+
+#[test]
+fn test_generic_for() {
+    let range = r#"
+        function range(n)
+            return function(limit, i)
+                if i < limit then return i + 1, i * 2 end
+            end, n, 0
+        end
+    "#;
+
+    let x = run_test_script(&format!("{} total = 0 for i in range(4) do total = total + i end return total", range));
+    assert_eq!(x, Ok(Value::Number(10.0)));
+
+    let x = run_test_script(&format!("{} total = 0 for i, double in range(3) do total = total + double end return total", range));
+    assert_eq!(x, Ok(Value::Number(6.0)));
+
+    let x = run_test_script(&format!("{} for i in range(10) do if i == 3 then return i end end", range));
+    assert_eq!(x, Ok(Value::Number(3.0)));
+
+    assert_eq!(run_test_script("for x in nil do end"), Err(LuaError::InvalidCall(Value::Nil)));
+}
+
+#[test]
+fn test_step_count() {
+    let mut interpreter = Interpreter::new();
+    assert_eq!(interpreter.last_step_count(), 0);
+
+    // Two statements, and four expressions: `1 + 2`, its operands, and `x`.
+    interpreter.execute("local x = 1 + 2 return x").expect("No errors");
+    assert_eq!(interpreter.last_step_count(), 6);
+
+    // The count is reset, and deterministic, for each execution.
+    let script = "total = 0 for i = 1, 10 do total = total + i end";
+    interpreter.execute(script).expect("No errors");
+    let count = interpreter.last_step_count();
+    interpreter.execute(script).expect("No errors");
+    assert_eq!(interpreter.last_step_count(), count);
+
+    // Steps are still counted up to an error.
+    assert!(interpreter.execute("x = 1 y = {} + 1").is_err());
+    assert_eq!(interpreter.last_step_count(), 6);
+}
+
+#[test]
+fn test_multiple_locals() {
+    assert_eq!(run_test_script("local a, b = 1 return b"), Ok(Value::Nil));
+    assert_eq!(run_test_script("local a, b = 1 return a"), Ok(Value::Number(1.0)));
+    assert_eq!(run_test_script("local a, b = 1, 2, 3 return a + b"), Ok(Value::Number(3.0)));
+    assert_eq!(run_test_script("local a return a"), Ok(Value::Nil));
+
+    // The last value expands to all of a call's results.
+    let f = "function f() return 1, 2, 3 end";
+    assert_eq!(run_test_script(&format!("{} local a, b, c = f() return a + b + c", f)), Ok(Value::Number(6.0)));
+    assert_eq!(run_test_script(&format!("{} local a, b, c = f(), 10 return c", f)), Ok(Value::Nil));
+    assert_eq!(run_test_script("function f(...) local a, b = ... return b end return f(1, 2)"), Ok(Value::Number(2.0)));
+
+    // Each name can have its own attribute.
+    assert_eq!(run_test_script("local a <const>, b = 1, 2 b = 3 return a + b"), Ok(Value::Number(4.0)));
+    assert_eq!(run_test_script("local a <const>, b = 1, 2 a = 3"), Err(LuaError::AssignToConst("a".to_owned())));
+}
+
+#[test]
+fn test_nesting_limit() {
+    let nested = |open: &str, close: &str, depth: usize| format!("return {}1{}", open.repeat(depth), close.repeat(depth));
+    assert!(run_test_script(&nested("{", "}", 200)).is_ok());
+    assert!(run_test_script(&nested("(", ")", 200)).is_ok());
+
+    for (open, close) in [("{", "}"), ("(", ")"), ("t[", "]")] {
+        match run_test_script(&nested(open, close, 10_000)) {
+            Err(LuaError::ParseError(ParseError { message, line, column })) => {
+                assert_eq!(message, "chunk has too many syntax levels");
+                // The error is at the first bracket past the limit.
+                assert_eq!((line, column), (1, "return ".len() + 201 * open.len()));
+            },
+            result => panic!("Expected a parse error, got {:?}", result),
+        }
+    }
+
+    // Only the depth of nesting counts, not the number of brackets.
+    assert!(run_test_script(&"x = {{}}\n".repeat(1000)).is_ok());
+
+    // Operators, blocks and function bodies nest in the same way.
+    let chain = |operator: &str, terms: usize| format!("return {}", vec!["1"; terms].join(operator));
+    assert_eq!(run_test_script(&chain(" + ", 200)), Ok(Value::Number(200.0)));
+    assert_eq!(run_test_script(&chain(" .. ", 200)), Ok(Value::String("1".repeat(200))));
+    assert_eq!(run_test_script(&nested("- ", "", 200)), Ok(Value::Number(1.0)));
+    let function = |depth: usize| format!("return {}1{}", "function() return ".repeat(depth), " end".repeat(depth));
+    assert!(run_test_script(&function(200)).is_ok());
+    assert!(run_test_script(&format!("{}x = 1{}", "do ".repeat(200), " end".repeat(200))).is_ok());
+
+    for script in [chain(" + ", 100_000), chain(" .. ", 100_000), nested("- ", "", 100_000), nested("#", "", 100_000),
+                   function(20_000), format!("{}x = 1{}", "do ".repeat(20_000), " end".repeat(20_000))] {
+        match run_test_script(&script) {
+            Err(LuaError::ParseError(ParseError { message, .. })) =>
+                assert_eq!(message, "chunk has too many syntax levels"),
+            result => panic!("Expected a parse error, got {:?}", result),
+        }
+    }
+
+    let mut interpreter = Interpreter::new();
+    interpreter.set_max_nesting_depth(2);
+    assert!(interpreter.execute("return {{}}").is_ok());
+    assert!(interpreter.execute("return {{{}}}").is_err());
+}
+
+#[test]
+fn test_vararg_outside_vararg_function() {
+    assert_eq!(run_test_script("function f() return ... end"), Err(LuaError::VarargOutsideVarargFunction));
+    assert_eq!(run_test_script("function f(...) return function() return ... end end"),
+        Err(LuaError::VarargOutsideVarargFunction));
+
+    // The main chunk is variadic, but has no arguments.
+    assert_eq!(run_test_script("return select(\"#\", ...)"), Ok(Value::Number(0.0)));
+
+    // `...` in return position expands all of the varargs.
+    assert_eq!(run_test_script("function f(...) return ... end return select(\"#\", f(1, 2, 3))"), Ok(Value::Number(3.0)));
+    assert_eq!(run_test_script("function f(a, ...) return ... end return f(1, 2)"), Ok(Value::Number(2.0)));
+}
+
+#[test]
+fn test_long_strings() {
+    let x = run_test_script("return [[\nfirst line\nsecond \\n line]]");
+    assert_eq!(x, Ok(Value::String("first line\nsecond \\n line".to_owned())));
+
+    // Only the closing bracket of the same level ends the string.
+    let x = run_test_script("return [==[a]]b]=]c]==]");
+    assert_eq!(x, Ok(Value::String("a]]b]=]c".to_owned())));
+
+    let x = run_test_script("return [[\"quoted\" -- not a comment]] .. [=[]=]");
+    assert_eq!(x, Ok(Value::String("\"quoted\" -- not a comment".to_owned())));
+
+    // Indexing with a nested index isn't mistaken for a long string.
+    assert_eq!(run_test_script("t = {1, 2} i = {2} return t[i[1]]"), Ok(Value::Number(2.0)));
+
+    // Long strings may be of any level, and keep their contents as written.
+    let x = run_test_script("return [====[\nhé ]===] \t \"]====] .. [[ ]] .. #[=====[\n\n]=====]");
+    assert_eq!(x, Ok(Value::String("hé ]===] \t \" 1".to_owned())));
+
+    let x = run_test_script("t = {[ [======[k]======] ] = 1} return t.k");
+    assert_eq!(x, Ok(Value::Number(1.0)));
+
+    match run_test_script("x = 1\nreturn [====[ ]===]") {
+        Err(LuaError::ParseError(ParseError { line, message, .. })) => {
+            assert_eq!(line, 2);
+            assert_eq!(message, "unfinished long string");
+        },
+        result => panic!("Expected a parse error, got {:?}", result),
+    }
+}
+
+#[test]
+fn test_block_scope() {
+    // Locals declared in a block don't outlive it.
+    assert_eq!(run_test_script("do local x = 1 end return x"), Ok(Value::Nil));
+    assert_eq!(run_test_script("if true then local x = 1 end return x"), Ok(Value::Nil));
+    assert_eq!(run_test_script("for i = 1, 2 do local x = i end return x"), Ok(Value::Nil));
+    assert_eq!(run_test_script("for i = 1, 2 do end return i"), Ok(Value::Nil));
+
+    // A local in a block shadows one of the same name outside of it.
+    assert_eq!(run_test_script("local x = 1 do local x = 2 end return x"), Ok(Value::Number(1.0)));
+
+    // But the block can still assign to locals from outside of it.
+    assert_eq!(run_test_script("local x = 1 do x = 2 end return x"), Ok(Value::Number(2.0)));
+    assert_eq!(run_test_script("local x = 0 for i = 1, 3 do x = x + i end return x"), Ok(Value::Number(6.0)));
+
+    // Redeclaring a local creates a new variable, rather than assigning to the old one.
+    let x = run_test_script(r#"
+        local x = 1
+        local get = function() return x end
+        local x = 2
+        return get() + x * 10
+    "#);
+    assert_eq!(x, Ok(Value::Number(21.0)));
+
+    // Parameters are new variables, even if they share a name with a captured one.
+    let x = run_test_script(r#"
+        local a = 1
+        local set = function(a) a = 5 end
+        set(2)
+        return a
+    "#);
+    assert_eq!(x, Ok(Value::Number(1.0)));
+}
+
+#[test]
+fn test_table_insert_and_remove() {
+    let contents = r#"
+        function contents(t)
+            local s = ""
+            for i = 1, #t do s = s .. t[i] end
+            return s
+        end
+    "#;
+    let run = |script: &str| run_test_script(&format!("{} {}", contents, script));
+
+    assert_eq!(run("t = {1, 2} table.insert(t, 3) return contents(t)"), string("123"));
+    assert_eq!(run("t = {1, 2} table.insert(t, 1, 0) return contents(t)"), string("012"));
+    assert_eq!(run("t = {1, 2} table.insert(t, 3, 3) return contents(t)"), string("123"));
+    assert_eq!(run("t = {1, 2, 3} return table.remove(t) .. contents(t)"), string("312"));
+    assert_eq!(run("t = {1, 2, 3} return table.remove(t, 1) .. contents(t)"), string("123"));
+    assert_eq!(run("t = {} return table.remove(t)"), Ok(Value::Nil));
+
+    // Building up an array, then taking it apart from the front.
+    let x = run_test_script(r#"
+        local queue = {}
+        for i = 1, 4 do table.insert(queue, i * 10) end
+        table.insert(queue, 3, 25)
+
+        local removed = table.remove(queue, 1) .. table.remove(queue, 1)
+        return removed .. ":" .. table.concat(queue, ",") .. ":" .. #queue
+    "#);
+    assert_eq!(x, string("1020:25,30,40:3"));
+
+    // Elements past a hole aren't part of the sequence, until it's filled.
+    assert_eq!(run("t = {1, 2, 3, [5] = 5} table.insert(t, 4) return #t"), Ok(Value::Number(5.0)));
+    assert_eq!(run("t = {1, 2, 3, [5] = 5} table.insert(t, 1, 0) return contents(t)"), string("01235"));
+    assert_eq!(run("t = {1, 2, 3, [5] = 5} table.remove(t, 1) return #t .. t[5]"), string("25"));
+    assert_eq!(run("t = {1, 2, 3} t[2] = nil table.insert(t, 2) return contents(t)"), string("123"));
+
+    assert_eq!(run_test_script("table.insert({1}, 3, 0)"),
+        Err(LuaError::BadArgument(2, "insert", "position out of bounds".to_owned())));
+    assert_eq!(run_test_script("table.insert({}, 1, 2, 3)"),
+        Err(LuaError::Custom("wrong number of arguments to 'insert'".to_owned())));
+    assert_eq!(run_test_script("table.remove({1}, 5)"),
+        Err(LuaError::BadArgument(2, "remove", "position out of bounds".to_owned())));
+    assert_eq!(run_test_script("table.insert(1, 2)"),
+        Err(LuaError::BadArgument(1, "insert", "table expected, got number".to_owned())));
+}
+
+#[test]
+fn test_version() {
+    let version = crate::version();
+    let (name, dialect) = version.split_once(", ").expect("Name and dialect");
+    assert_eq!(name, format!("RustLua {}", env!("CARGO_PKG_VERSION")));
+    assert!(name.split(' ').nth(1).unwrap().split('.').all(|part| part.parse::<u32>().is_ok()));
+    assert_eq!(dialect, "Lua 5.4 subset");
+}
+
+#[test]
+fn test_shared_upvalues() {
+    // Closures created in the same scope share its variables with each other,
+    // and with the scope itself.
+    let x = run_test_script(r#"
+        function make()
+            local n = 0
+            local increment = function() n = n + 1 end
+            local get = function() return n end
+            increment()
+            return increment, get, n
+        end
+
+        local increment, get, initial = make()
+        increment()
+        increment()
+        return get() * 10 + initial
+    "#);
+    assert_eq!(x, Ok(Value::Number(31.0)));
+
+    let x = run_test_script(r#"
+        local n = 0
+        local increment = function() n = n + 1 end
+        increment()
+        increment()
+        return n
+    "#);
+    assert_eq!(x, Ok(Value::Number(2.0)));
+}
+
+#[test]
+fn test_number_literals() {
+    assert_eq!(run_test_script("return 0xff == 255"), Ok(Value::Boolean(true)));
+    assert_eq!(run_test_script("return 1e3 == 1000"), Ok(Value::Boolean(true)));
+    assert_eq!(run_test_script("return 2e-2 == 0.02"), Ok(Value::Boolean(true)));
+    assert_eq!(run_test_script("return 0xA + 1e1 - 0x1.8p1"), Ok(Value::Number(17.0)));
+}
+
+#[test]
+fn test_local_function() {
+    let x = run_test_script(r#"
+        local function fact(n)
+            if n <= 1 then return 1 end
+            return n * fact(n - 1)
+        end
+
+        return fact(5)
+    "#);
+    assert_eq!(x, Ok(Value::Number(120.0)));
+
+    // Unlike a function declaration, it doesn't touch the global of the same name.
+    let x = run_test_script(r#"
+        fact = 1
+        do
+            local function fact(n)
+                if n <= 1 then return 1 end
+                return n * fact(n - 1)
+            end
+            fact = fact(4)
+        end
+        return fact
+    "#);
+    assert_eq!(x, Ok(Value::Number(1.0)));
+
+    // It's a local, so isn't visible outside of its block.
+    assert_eq!(run_test_script("do local function f() return 1 end end return f"), Ok(Value::Nil));
+
+    // Whereas with a plain local, the name in the body isn't the local yet.
+    let x = run_test_script(r#"
+        local fact = function(n)
+            if n <= 1 then return 1 end
+            return n * fact(n - 1)
+        end
+
+        return fact(5)
+    "#);
+    assert_eq!(x, Err(LuaError::UpvalueBeforeDeclaration("fact".to_owned())));
+}
+
+#[test]
+fn test_integer_valued_floats() {
+    assert_eq!(run_test_script("return 2 == 2.0"), Ok(Value::Boolean(true)));
+    assert_eq!(run_test_script("return 4 / 2 == 2"), Ok(Value::Boolean(true)));
+
+    // They address the same table slot, including the length.
+    assert_eq!(run_test_script("t = {} t[2] = \"a\" return t[2.0]"), Ok(Value::String("a".to_owned())));
+    assert_eq!(run_test_script("t = {} t[1.0] = 1 t[4 / 2] = 2 t[0.5 * 6] = 3 return #t"), Ok(Value::Number(3.0)));
+    assert_eq!(run_test_script("t = {} t[0] = 1 return t[-0]"), Ok(Value::Number(1.0)));
+
+    // But are distinct from strings, and other floats.
+    assert_eq!(run_test_script("t = { [\"2\"] = \"s\" } t[2] = \"n\" return t[\"2\"]"), Ok(Value::String("s".to_owned())));
+    assert_eq!(run_test_script("t = {} t[2] = 1 return t[2.5]"), Ok(Value::Nil));
+    assert_eq!(run_test_script("t = {} t[2.5] = 1 return t[\"2.5\"]"), Ok(Value::Nil));
+    assert_eq!(run_test_script("t = {} t[2.5] = 1 return t[5 / 2]"), Ok(Value::Number(1.0)));
+}
+
+#[test]
+fn test_metatables() {
+    assert_eq!(run_test_script("t = {} mt = {} return setmetatable(t, mt) == t"), Ok(Value::Boolean(true)));
+    assert_eq!(run_test_script("t = {} mt = {} setmetatable(t, mt) return getmetatable(t) == mt"), Ok(Value::Boolean(true)));
+    assert_eq!(run_test_script("t = setmetatable({}, {}) setmetatable(t, nil) return getmetatable(t)"), Ok(Value::Nil));
+    assert_eq!(run_test_script("return getmetatable(1)"), Ok(Value::Nil));
+
+    // Missing fields are looked up in the `__index` table.
+    let x = run_test_script(r#"
+        base = { greeting = "hello", name = "base" }
+        t = setmetatable({ name = "t" }, { __index = base })
+        return t.greeting .. " " .. t.name .. " " .. t["greeting"]
+    "#);
+    assert_eq!(x, Ok(Value::String("hello t hello".to_owned())));
+
+    // Including in method calls.
+    let x = run_test_script(r#"
+        Counter = { n = 0 }
+        function Counter:inc() self.n = self.n + 1 return self.n end
+
+        c = setmetatable({ n = 10 }, { __index = Counter })
+        return c:inc()
+    "#);
+    assert_eq!(x, Ok(Value::Number(11.0)));
+
+    // A `__metatable` field protects the metatable.
+    let x = run_test_script("t = setmetatable({}, { __metatable = \"locked\" }) return getmetatable(t)");
+    assert_eq!(x, Ok(Value::String("locked".to_owned())));
+    let x = run_test_script("t = setmetatable({}, { __metatable = false }) setmetatable(t, {})");
+    assert_eq!(x, Err(LuaError::Custom("cannot change a protected metatable".to_owned())));
+
+    assert_eq!(run_test_script("setmetatable({}, 1)"),
+        Err(LuaError::BadArgument(2, "setmetatable", "nil or table expected, got number".to_owned())));
+}
+
+#[test]
+fn test_isolated_functions() {
+    let script = r#"
+        secret = 42
+        local function read() return secret end
+        return read()
+    "#;
+    assert_eq!(run_test_script(script), Ok(Value::Number(42.0)));
+
+    let mut interpreter = Interpreter::new();
+    interpreter.set_isolated_functions(true);
+    assert_eq!(interpreter.execute(script), Ok(Value::Nil));
+
+    // Captured locals and arguments are still visible, as are globals in
+    // the main chunk.
+    let x = interpreter.execute(r#"
+        secret = 1
+        local captured = 2
+        local function add(n) return captured + n end
+        return add(secret)
+    "#);
+    assert_eq!(x, Ok(Value::Number(3.0)));
+
+    // Defining a function in a table from a global reads the global too.
+    interpreter.execute("handlers = {}").expect("No errors");
+    let x = interpreter.execute(r#"
+        local function install() function handlers.on_load() end end
+        install()
+    "#);
+    assert_eq!(x, Err(LuaError::InvalidIndex(Value::Nil)));
+    assert_eq!(interpreter.execute("return type(handlers.on_load)"), string("nil"));
+}
+
+#[test]
+fn test_string_format_missing_argument() {
+    assert_eq!(run_test_script("return string.format(\"%d %d\", 1)"),
+        Err(LuaError::BadArgument(3, "format", "no value".to_owned())));
+    assert_eq!(run_test_script("return string.format(\"%s\")"),
+        Err(LuaError::BadArgument(2, "format", "no value".to_owned())));
+    assert_eq!(run_test_script("return string.format(\"%c\")"),
+        Err(LuaError::BadArgument(2, "format", "no value".to_owned())));
+
+    // Extra arguments are ignored.
+    assert_eq!(run_test_script("return string.format(\"%d\", 1, 2)"), Ok(Value::String("1".to_owned())));
+    assert_eq!(run_test_script("return string.format(\"%5i|%-3d|\", 42, 7)"), Ok(Value::String("   42|7  |".to_owned())));
+    assert_eq!(run_test_script("return string.format(\"%d %i %d\", 3.7, -3.7, 0.5)"), Ok(Value::String("3 -3 0".to_owned())));
+
+    // Numbers outside the range of an integer have no representation either.
+    assert_eq!(run_test_script("return string.format(\"%d\", -2^63)"), Ok(Value::String("-9223372036854775808".to_owned())));
+    for n in ["2^63", "-2^64", "1/0", "0/0"] {
+        assert_eq!(run_test_script(&format!("return string.format(\"%d\", {})", n)),
+            Err(LuaError::BadArgument(2, "format", "number has no integer representation".to_owned())), "{}", n);
+    }
+}
+
+#[test]
+fn test_type() {
+    assert_eq!(run_test_script("return type(1)"), string("number"));
+    assert_eq!(run_test_script("return type(\"x\")"), string("string"));
+    assert_eq!(run_test_script("return type({})"), string("table"));
+    assert_eq!(run_test_script("return type(type)"), string("function"));
+    assert_eq!(run_test_script("return type(function() end)"), string("function"));
+    assert_eq!(run_test_script("return type(nil)"), string("nil"));
+    assert_eq!(run_test_script("return type(1 == 1)"), string("boolean"));
+
+    assert_eq!(run_test_script("return type(math.floor)"), string("function"));
+
+    // Exactly one argument is taken, even where it's nil.
+    assert_eq!(run_test_script("return type(1, {})"),
+        Err(LuaError::BadArgument(2, "type", "wrong number of arguments".to_owned())));
+    assert_eq!(run_test_script("return type(nil, nil)"),
+        Err(LuaError::BadArgument(2, "type", "wrong number of arguments".to_owned())));
+    assert_eq!(run_test_script("return type()"), Err(LuaError::BadArgument(1, "type", "value expected".to_owned())));
+}
+
+#[test]
+fn test_index_metamethod() {
+    // Prototype chains are followed through each `__index` table.
+    let x = run_test_script(r#"
+        Animal = { legs = 4 }
+        Animal.__index = Animal
+        function Animal:describe() return self.name .. " has " .. self.legs .. " legs" end
+
+        Dog = setmetatable({ sound = "woof" }, Animal)
+        Dog.__index = Dog
+
+        fido = setmetatable({ name = "fido" }, Dog)
+        return fido:describe() .. ", " .. fido.sound
+    "#);
+    assert_eq!(x, Ok(Value::String("fido has 4 legs, woof".to_owned())));
+
+    // A function is called with the table and the missing index.
+    let x = run_test_script(r#"
+        t = setmetatable({ a = 1 }, { __index = function(t, k) return k .. "!" end })
+        return t.a .. t.b .. t[2]
+    "#);
+    assert_eq!(x, Ok(Value::String("1b!2!".to_owned())));
+
+    let x = run_test_script("mt = {} mt.__index = setmetatable({}, mt) t = setmetatable({}, mt) return t.x");
+    assert_eq!(x, Err(LuaError::Custom("'__index' chain too long; possibly a loop".to_owned())));
+}
+
+#[test]
+fn test_loop_closures() {
+    // Each iteration's closure captures its own loop variable.
+    let x = run_test_script(r#"
+        local t = {}
+        for i = 1, 3 do
+            t[i] = function() return i end
+        end
+
+        return t[1]() .. t[2]() .. t[3]()
+    "#);
+    assert_eq!(x, Ok(Value::String("123".to_owned())));
+
+    // And its own copy of locals declared in the body.
+    let x = run_test_script(r#"
+        local t = {}
+        for i = 1, 2 do
+            local upper = ({ "a", "b" })[i]
+            upper = upper .. upper
+            t[#t + 1] = function() return upper end
+        end
+
+        return t[1]() .. t[2]()
+    "#);
+    assert_eq!(x, Ok(Value::String("aabb".to_owned())));
+}
+
+#[test]
+fn test_tostring() {
+    assert_eq!(run_test_script("return tostring(12)"), string("12"));
+    assert_eq!(run_test_script("return tostring(1.5)"), string("1.5"));
+    assert_eq!(run_test_script("return tostring(true) .. tostring(false)"), string("truefalse"));
+    assert_eq!(run_test_script("return tostring(nil)"), string("nil"));
+    assert_eq!(run_test_script("return tostring(\"x\")"), string("x"));
+
+    let x = run_test_script("return tostring({})");
+    assert!(matches!(x, Ok(Value::String(s)) if s.starts_with("table: ")));
+
+    // Tables can define their own conversion.
+    let x = run_test_script(r#"
+        point = setmetatable({ x = 1, y = 2 }, {
+            __tostring = function(p) return "(" .. p.x .. ", " .. p.y .. ")" end,
+        })
+        return tostring(point)
+    "#);
+    assert_eq!(x, string("(1, 2)"));
+
+    // Displaying a value gives the same as `tostring`, other than metamethods.
+    assert_eq!(Value::Nil.to_string(), "nil");
+    assert_eq!(Value::Number(3.0).to_string(), "3");
+    assert_eq!(Value::Number(0.1).to_string(), "0.1");
+    assert_eq!(Value::Number(1e100).to_string(), "1e+100");
+
+    // Formatting with `%s` calls `__tostring` too.
+    let x = run_test_script("return string.format(\"<%s>\", setmetatable({}, { __tostring = function() return \"obj\" end }))");
+    assert_eq!(x, string("<obj>"));
+
+    // Displaying a table gives its address, rather than its contents.
+    let table = Value::Table(Default::default());
+    assert_eq!(table.to_string(), table.to_lua_string());
+    assert!(table.to_string().starts_with("table: 0x"));
+
+    let x = run_test_script("return tostring(setmetatable({}, { __name = \"Point\" }))");
+    assert!(matches!(x, Ok(Value::String(s)) if s.starts_with("Point: ")));
+
+    let x = run_test_script("return tostring(setmetatable({}, { __tostring = function() return {} end }))");
+    assert_eq!(x, Err(LuaError::Custom("'__tostring' must return a string".to_owned())));
+}
+
+#[test]
+fn test_newindex_metamethod() {
+    // New keys are passed to a `__newindex` function, existing ones aren't.
+    let x = run_test_script(r#"
+        log = ""
+        t = setmetatable({ a = 1 }, {
+            __newindex = function(t, k, v) log = log .. k .. "=" .. v .. " " end,
+        })
+
+        t.a = 2
+        t.b = 3
+        t[4] = 5
+        return log .. t.a .. " " .. tostring(t.b)
+    "#);
+    assert_eq!(x, Ok(Value::String("b=3 4=5 2 nil".to_owned())));
+
+    // Or set in a `__newindex` table instead, including by function declarations.
+    let x = run_test_script(r#"
+        store = {}
+        proxy = setmetatable({}, { __newindex = store })
+        proxy.x = 1
+        function proxy.f() return 2 end
+        return tostring(proxy.x) .. store.x .. store.f()
+    "#);
+    assert_eq!(x, Ok(Value::String("nil12".to_owned())));
+
+    // `rawset` bypasses the metamethod.
+    let x = run_test_script(r#"
+        called = false
+        t = setmetatable({}, { __newindex = function() called = true end })
+        rawset(t, "x", 1)
+        t.x = 2
+        return tostring(called) .. t.x
+    "#);
+    assert_eq!(x, Ok(Value::String("false2".to_owned())));
+}
+
+#[test]
+fn test_math_random() {
+    let x = run_test_script(r#"
+        for i = 1, 1000 do
+            local n = math.random(1, 6)
+            if n < 1 or n > 6 or math.floor(n) ~= n then
+                return n
+            end
+        end
+
+        return true
+    "#);
+    assert_eq!(x, Ok(Value::Boolean(true)));
+
+    let x = run_test_script(r#"
+        for i = 1, 1000 do
+            local a, b = math.random(), math.random(3)
+            if a < 0 or a >= 1 or b < 1 or b > 3 then
+                return false
+            end
+        end
+
+        return math.random(5, 5)
+    "#);
+    assert_eq!(x, Ok(Value::Number(5.0)));
+
+    assert_eq!(run_test_script("return math.random(6, 1)"),
+        Err(LuaError::BadArgument(2, "random", "interval is empty".to_owned())));
+    assert_eq!(run_test_script("return math.random(-1)"),
+        Err(LuaError::BadArgument(1, "random", "interval is empty".to_owned())));
+    assert_eq!(run_test_script("return math.random(1, 2, 3)"),
+        Err(LuaError::Custom("wrong number of arguments".to_owned())));
+}
+
+#[test]
+fn test_tonumber() {
+    assert_eq!(run_test_script("return tonumber(\"42\")"), Ok(Value::Number(42.0)));
+    assert_eq!(run_test_script("return tonumber(\" 0x10 \")"), Ok(Value::Number(16.0)));
+    assert_eq!(run_test_script("return tonumber(\"1.5e2\")"), Ok(Value::Number(150.0)));
+    assert_eq!(run_test_script("return tonumber(7)"), Ok(Value::Number(7.0)));
+
+    // Anything that isn't a number gives nil.
+    assert_eq!(run_test_script("return tonumber(\"abc\")"), Ok(Value::Nil));
+    assert_eq!(run_test_script("return tonumber({})"), Ok(Value::Nil));
+    assert_eq!(run_test_script("return tonumber(nil)"), Ok(Value::Nil));
+
+    // Like the reference implementation, special float words aren't numbers.
+    for s in ["inf", "-inf", "nan", "infinity"] {
+        assert_eq!(run_test_script(&format!("return tonumber(\"{}\")", s)), Ok(Value::Nil), "{}", s);
+    }
+    assert_eq!(run_test_script("return tonumber(\"0xff\")"), Ok(Value::Number(255.0)));
+    assert_eq!(run_test_script("return tonumber(\"0x1p99999999999\")"), Ok(Value::Number(f64::INFINITY)));
+    assert_eq!(run_test_script("return tonumber(\"\t-2.5 \")"), Ok(Value::Number(-2.5)));
+
+    assert_eq!(run_test_script("return tonumber(\"ff\", 16)"), Ok(Value::Number(255.0)));
+    assert_eq!(run_test_script("return tonumber(\"101\", 2)"), Ok(Value::Number(5.0)));
+    assert_eq!(run_test_script("return tonumber(\"-Zz\", 36)"), Ok(Value::Number(-1295.0)));
+    assert_eq!(run_test_script("return tonumber(\"12\", 2)"), Ok(Value::Nil));
+    assert_eq!(run_test_script("return tonumber(\"\", 10)"), Ok(Value::Nil));
+
+    assert_eq!(run_test_script("return tonumber(\"1\", 37)"),
+        Err(LuaError::BadArgument(2, "tonumber", "base out of range".to_owned())));
+    assert_eq!(run_test_script("return tonumber(10, 16)"),
+        Err(LuaError::BadArgument(1, "tonumber", "string expected, got number".to_owned())));
+    assert_eq!(run_test_script("return tonumber()"),
+        Err(LuaError::BadArgument(1, "tonumber", "value expected".to_owned())));
+}
+
+#[test]
+fn test_arithmetic_metamethods() {
+    let x = run_test_script(r#"
+        Vector = {}
+        Vector.__index = Vector
+        function Vector.new(x, y) return setmetatable({ x = x, y = y }, Vector) end
+        function Vector.__add(a, b) return Vector.new(a.x + b.x, a.y + b.y) end
+        function Vector.__sub(a, b) return Vector.new(a.x - b.x, a.y - b.y) end
+        function Vector.__mul(a, b)
+            if type(a) == "number" then return Vector.new(a * b.x, a * b.y) end
+            return Vector.new(a.x * b, a.y * b)
+        end
+        function Vector.__div(a, b) return Vector.new(a.x / b, a.y / b) end
+
+        local v = (Vector.new(1, 2) + Vector.new(3, 4) - Vector.new(1, 1)) * 2
+        local w = 3 * v / 6
+        return v.x .. "," .. v.y .. " " .. w.x .. "," .. w.y
+    "#);
+    assert_eq!(x, Ok(Value::String("6,10 3,5".to_owned())));
+
+    // Without a metamethod on either operand, it's still an error.
+    let x = run_test_script("return setmetatable({}, {}) + 1");
+    assert!(matches!(x, Err(LuaError::InvalidArithmetic(Value::Table(_)))));
+    let x = run_test_script("return 1 - setmetatable({}, { __add = function() return 0 end })");
+    assert!(matches!(x, Err(LuaError::InvalidArithmetic(Value::Table(_)))));
+}
+
+#[test]
+fn test_unary_metamethods() {
+    let x = run_test_script(r#"
+        local mt = {}
+        function mt.__unm(a, b) return setmetatable({ n = -a.n, same = a == b }, mt) end
+        function mt.__len(t) return t.n end
+        local v = -setmetatable({ n = 3 }, mt)
+        return #v .. " " .. type(v.same)
+    "#);
+    assert_eq!(x, Ok(Value::String("-3 boolean".to_owned())));
+
+    // `__len` takes the place of the border, even for a table with items.
+    let x = run_test_script("return #setmetatable({ 1, 2, 3 }, { __len = function() return 10 end })");
+    assert_eq!(x, Ok(Value::Number(10.0)));
+
+    // Without a metamethod, tables use their border and can't be negated.
+    assert_eq!(run_test_script("return #setmetatable({ 1, 2 }, {})"), Ok(Value::Number(2.0)));
+    let x = run_test_script("return -setmetatable({}, {})");
+    assert!(matches!(x, Err(LuaError::InvalidArithmetic(Value::Table(_)))));
+}
+
+#[test]
+fn test_eq_metamethod() {
+    let x = run_test_script(r#"
+        local mt = { __eq = function(a, b) return a.id == b.id end }
+        local a = setmetatable({ id = 1 }, mt)
+        local b = setmetatable({ id = 1 }, mt)
+        local c = { id = 2 }
+        local d = { id = 1 }
+        return tostring(a == b) .. tostring(a ~= b) .. tostring(c == a) .. tostring(d == a)
+    "#);
+    assert_eq!(x, Ok(Value::String("truefalsefalsetrue".to_owned())));
+
+    // The result is converted to a boolean, and other types never consult it.
+    let x = run_test_script(r#"
+        local a = setmetatable({}, { __eq = function() return 1 end })
+        return a == {}
+    "#);
+    assert_eq!(x, Ok(Value::Boolean(true)));
+    let x = run_test_script("return setmetatable({}, { __eq = function() return true end }) == 1");
+    assert_eq!(x, Ok(Value::Boolean(false)));
+}
+
+#[test]
+fn test_snapshot_and_restore() {
+    let mut interpreter = Interpreter::new();
+    interpreter.execute("x = 1 t = { a = 1 }").expect("No errors");
+
+    let snapshot = interpreter.snapshot();
+    interpreter.execute("x = 2 y = 3 t = { a = 2 } type = nil").expect("No errors");
+    interpreter.restore(snapshot);
+    assert_eq!(interpreter.execute("return x"), Ok(Value::Number(1.0)));
+    assert_eq!(interpreter.execute("return y"), Ok(Value::Nil));
+    assert_eq!(interpreter.execute("return t.a"), Ok(Value::Number(1.0)));
+    assert_eq!(interpreter.execute("return type(_G)"), Ok(Value::String("table".to_owned())));
+
+    // Changes to the contents of a table held in a global are kept.
+    let snapshot = interpreter.snapshot();
+    interpreter.execute("t.a = 3").expect("No errors");
+    interpreter.restore(snapshot);
+    assert_eq!(interpreter.execute("return t.a"), Ok(Value::Number(3.0)));
+}
+
+#[test]
+fn test_assert() {
+    assert_eq!(run_test_script("return select(\"#\", assert(1, 2, 3))"), Ok(Value::Number(3.0)));
+    assert_eq!(run_test_script("return assert(\"value\", \"unused\")"), Ok(Value::String("value".to_owned())));
+
+    assert_eq!(run_test_script("assert(false, \"boom\")"), Err(LuaError::Runtime(Value::String("boom".to_owned()))));
+    assert_eq!(run_test_script("assert(nil)"), Err(LuaError::Custom("assertion failed!".to_owned())));
+    assert_eq!(run_test_script("assert()"), Err(LuaError::BadArgument(1, "assert", "value expected".to_owned())));
+    assert_eq!(run_test_script("return select(2, pcall(assert, 1 == 2, \"no\"))"), Ok(Value::String("no".to_owned())));
+}
+
+#[test]
+fn test_assert_passes_arguments_through() {
+    let x = run_test_script(r#"
+        local function open(name)
+            return name, "mode"
+        end
+
+        local file, mode = assert(open("data"))
+        assert(1 == 1)
+        return file .. " " .. mode
+    "#);
+    assert_eq!(x, Ok(Value::String("data mode".to_owned())));
+
+    // Any value can be the message, and is raised as it is.
+    let x = run_test_script(r#"
+        local message = { code = 2 }
+        local ok, err = pcall(assert, false, message)
+        return ok == false and err == message
+    "#);
+    assert_eq!(x, Ok(Value::Boolean(true)));
+    assert_eq!(run_test_script("assert(false, \"oops\")"), Err(LuaError::Runtime(Value::String("oops".to_owned()))));
+}
+
+#[cfg(feature = "io")]
+#[test]
+fn test_io_open() {
+    let path = std::env::temp_dir().join(format!("rust-lua-test-{}.txt", std::process::id()));
+    let path = path.to_str().expect("UTF-8 path").replace('\\', "\\\\");
+
+    let x = run_test_script(&format!(r#"
+        local path = "{}"
+        local file = io.open(path, "w")
+        file:write([[first line
+]], 2, [[
+
+]]):write("last")
+        file:close()
+
+        file = io.open(path)
+        local first, second = file:read("l", "n")
+        local rest = file:read("a")
+        file:close()
+
+        local count = 0
+        local lines = io.open(path, "r")
+        for line in lines:lines() do count = count + 1 end
+        lines:close()
+
+        return first .. "|" .. second .. "|" .. rest .. "|" .. count
+    "#, path));
+    std::fs::remove_file(&path).ok();
+    assert_eq!(x, Ok(Value::String("first line|2|last|3".to_owned())));
+
+    assert!(matches!(run_test_script("return select(2, io.open(\"/nonexistent/file\"))"), Ok(Value::String(_))));
+    assert_eq!(run_test_script("f = io.open(\"/nonexistent/file\", \"w\") return f"), Ok(Value::Nil));
+
+    // A closed file can't be used, even through methods taken from it
+    // before it was closed.
+    let x = run_test_script(&format!(r#"
+        local file = io.open("{}", "w")
+        local write = file.write
+        file:close()
+        local ok, message = pcall(write, file, "x")
+        return tostring(ok) .. " " .. message
+    "#, path));
+    std::fs::remove_file(&path).ok();
+    assert_eq!(x, Ok(Value::String("false attempt to use a closed file".to_owned())));
+}
+
+#[test]
+fn test_error() {
+    assert_eq!(run_test_script("error(\"x\")"), Err(LuaError::Runtime(Value::String("chunk:1: x".to_owned()))));
+    assert_eq!(run_test_script("error()"), Err(LuaError::Runtime(Value::Nil)));
+    assert_eq!(LuaError::Runtime(Value::Number(1.0)).to_string(), "1");
+    assert_eq!(LuaError::Runtime(Value::Boolean(true)).to_string(), "(error object is a boolean value)");
+
+    // Code after the error doesn't run.
+    let x = run_test_script(r#"
+        ran = false
+        pcall(function() error("stop") ran = true end)
+        return ran
+    "#);
+    assert_eq!(x, Ok(Value::Boolean(false)));
+
+    // `pcall` gives back the value the error was raised with.
+    let x = run_test_script(r#"
+        local ok, err = pcall(error, { code = 42 })
+        return tostring(ok) .. err.code
+    "#);
+    assert_eq!(x, Ok(Value::String("false42".to_owned())));
+
+    // The level chooses whose position is added to string messages.
+    let script = |level: &str| format!(r#"
+        local function f()
+            error("boom"{})
+        end
+        local ok, err = pcall(function()
+            f()
+        end)
+        return err
+    "#, level);
+    assert_eq!(run_test_script(&script("")), Ok(Value::String("chunk:3: boom".to_owned())));
+    assert_eq!(run_test_script(&script(", 1")), Ok(Value::String("chunk:3: boom".to_owned())));
+    assert_eq!(run_test_script(&script(", 2")), Ok(Value::String("chunk:6: boom".to_owned())));
+    assert_eq!(run_test_script(&script(", 0")), Ok(Value::String("boom".to_owned())));
+
+    // Functions called from native code, and levels past the main chunk, have
+    // no position, and other values never get one.
+    assert_eq!(run_test_script("return select(2, pcall(error, \"boom\"))"), Ok(Value::String("boom".to_owned())));
+    assert_eq!(run_test_script("error(\"boom\", 5)"), Err(LuaError::Runtime(Value::String("boom".to_owned()))));
+    assert_eq!(run_test_script("error(42)"), Err(LuaError::Runtime(Value::Number(42.0))));
+    assert_eq!(run_test_script("error(\"boom\", {})"),
+        Err(LuaError::BadArgument(2, "error", "number expected, got table".to_owned())));
+
+    let mut interpreter = Interpreter::new();
+    interpreter.set_chunk_name("script.lua");
+    assert_eq!(interpreter.execute("\nerror(\"boom\")"), Err(LuaError::Runtime(Value::String("script.lua:2: boom".to_owned()))));
+}
+
+#[test]
+fn test_define_fn() {
+    let mut interpreter = Interpreter::new();
+    interpreter.define_fn("pi", || std::f64::consts::PI);
+    interpreter.define_fn("greet", |arguments: Vec<Value>| format!("hello {}", arguments[0].to_lua_string()));
+    assert_eq!(interpreter.execute("return pi() * 2"), Ok(Value::Number(std::f64::consts::PI * 2.0)));
+    assert_eq!(interpreter.execute("return greet(\"lua\")"), Ok(Value::String("hello lua".to_owned())));
+    assert_eq!(interpreter.execute("return type(pi) .. tostring(pi == pi)"), Ok(Value::String("functiontrue".to_owned())));
+
+    // They can capture state.
+    let calls = std::rc::Rc::new(std::cell::Cell::new(0));
+    let counter = calls.clone();
+    interpreter.define_fn("count", move || {
+        counter.set(counter.get() + 1);
+        counter.get() as f64
+    });
+    assert_eq!(interpreter.execute("count() return count()"), Ok(Value::Number(2.0)));
+    assert_eq!(calls.get(), 2);
+}
+
+#[test]
+fn test_table_concat_defaults() {
+    assert_eq!(run_test_script("return table.concat({\"a\", \"b\", \"c\"})"), string("abc"));
+    assert_eq!(run_test_script("return table.concat({\"a\", \"b\", \"c\"}, \",\")"), string("a,b,c"));
+    assert_eq!(run_test_script("return table.concat({\"a\", \"b\", \"c\"}, \",\", 2)"), string("b,c"));
+    assert_eq!(run_test_script("return table.concat({\"a\", \"b\", \"c\"}, nil, 1, 2)"), string("ab"));
+    assert_eq!(run_test_script("return table.concat({}, \",\")"), string(""));
+    assert_eq!(run_test_script("return table.concat({\"a\"}, \",\", 3, 2)"), string(""));
+
+    // Only the sequence up to the border is joined by default.
+    assert_eq!(run_test_script("return table.concat({\"a\", \"b\", nil, \"d\"}, \",\")"), string("a,b"));
+    assert_eq!(run_test_script("t = {\"a\"} t[3] = \"c\" return table.concat(t, \",\")"), string("a"));
+}
+
+#[test]
+fn test_concat_metamethod() {
+    let x = run_test_script(r#"
+        Builder = {}
+        Builder.__index = Builder
+        function Builder.__concat(builder, s)
+            if type(builder) ~= "table" then
+                builder, s = s, builder
+            end
+
+            builder.buffer[#builder.buffer + 1] = s
+            return builder
+        end
+        function Builder:build() return table.concat(self.buffer, " ") end
+
+        local b = setmetatable({ buffer = {} }, Builder)
+        b = b .. "hello" .. "world"
+        b = 1 .. b
+        return b:build()
+    "#);
+    assert_eq!(x, Ok(Value::String("helloworld 1".to_owned())));
+
+    // Strings and numbers are still joined directly.
+    assert_eq!(run_test_script("return 1 .. 2"), Ok(Value::String("12".to_owned())));
+    assert!(matches!(run_test_script("return {} .. \"x\""), Err(LuaError::InvalidConcat(Value::Table(_)))));
+}
+
+#[test]
+fn test_table_concat() {
+    assert_eq!(run_test_script("return table.concat({1, 2, 3}, \"-\")"), Ok(Value::String("1-2-3".to_owned())));
+    assert_eq!(run_test_script("return table.concat({1.5, \"x\", 2}, \" \")"), Ok(Value::String("1.5 x 2".to_owned())));
+
+    assert_eq!(run_test_script("return table.concat({1, {}, 3})"),
+        Err(LuaError::Custom("invalid value (at index 2) in table for 'concat'".to_owned())));
+    assert_eq!(run_test_script("return table.concat({1, 2}, \",\", 1, 3)"),
+        Err(LuaError::Custom("invalid value (at index 3) in table for 'concat'".to_owned())));
+    assert_eq!(run_test_script("return table.concat({1}, {})"),
+        Err(LuaError::BadArgument(2, "concat", "string expected, got table".to_owned())));
+}
+
+#[test]
+fn test_print_forwarding() {
+    // All of the values passed through `...` reach the native function.
+    let printed = std::rc::Rc::new(std::cell::RefCell::new(Vec::new()));
+    let output = printed.clone();
+
+    let mut interpreter = Interpreter::new();
+    interpreter.define_fn("print", move |arguments: Vec<Value>| output.borrow_mut().push(arguments));
+    interpreter.execute("function f(...) print(...) end f(1, 2, 3) f() f(nil, \"x\")").expect("No errors");
+    assert_eq!(*printed.borrow(), vec![
+        vec![Value::Number(1.0), Value::Number(2.0), Value::Number(3.0)],
+        vec![],
+        vec![Value::Nil, Value::String("x".to_owned())],
+    ]);
+
+    // Which `print` writes separated by tabs.
+    let line = crate::print_line(&mut interpreter, &[Value::Number(1.0), Value::Number(2.0), Value::Number(3.0)]);
+    assert_eq!(line, Ok("1\t2\t3".to_owned()));
+    assert_eq!(crate::print_line(&mut interpreter, &[Value::Nil, Value::Boolean(true)]), Ok("nil\ttrue".to_owned()));
+    assert_eq!(crate::print_line(&mut interpreter, &[]), Ok(String::new()));
+}
+
+#[test]
+fn test_table_sort() {
+    assert_eq!(run_test_script("t = {3, 1, 2, 5, 4} table.sort(t) return table.concat(t, \",\")"), string("1,2,3,4,5"));
+    assert_eq!(run_test_script("t = {\"b\", \"c\", \"a\"} table.sort(t) return table.concat(t)"), string("abc"));
+    assert_eq!(run_test_script("t = {3, 1, 2} table.sort(t, function(a, b) return a > b end) return table.concat(t)"), string("321"));
+    assert_eq!(run_test_script("t = {} table.sort(t) return #t"), Ok(Value::Number(0.0)));
+
+    // Equal elements keep their order.
+    let x = run_test_script(r#"
+        t = { { k = 1, v = "a" }, { k = 0, v = "b" }, { k = 1, v = "c" }, { k = 0, v = "d" } }
+        table.sort(t, function(a, b) return a.k < b.k end)
+        return t[1].v .. t[2].v .. t[3].v .. t[4].v
+    "#);
+    assert_eq!(x, string("bdac"));
+
+    // Errors from the comparison stop the sort, leaving the table unchanged.
+    let x = run_test_script(r#"
+        t = {3, 1, 2}
+        local ok, err = pcall(table.sort, t, function(a, b) error("bad compare") end)
+        return err .. " " .. table.concat(t)
+    "#);
+    assert_eq!(x, string("chunk:3: bad compare 312"));
+    assert_eq!(run_test_script("table.sort({1, \"x\"})"),
+        Err(LuaError::InvalidComparison(Value::String("x".to_owned()), Value::Number(1.0))));
+
+    // An inconsistent comparison still finishes, with every element kept.
+    let x = run_test_script(r#"
+        t = {}
+        for i = 1, 50 do t[i] = i % 7 end
+        table.sort(t, function(a, b) return true end)
+
+        local sum = 0
+        for i = 1, #t do sum = sum + t[i] end
+        return #t .. " " .. sum
+    "#);
+    assert_eq!(x, string("50 148"));
+
+    assert_eq!(run_test_script("table.sort({}, 1)"),
+        Err(LuaError::BadArgument(2, "sort", "function expected, got number".to_owned())));
+}
+
+#[test]
+fn test_math_floor_results() {
+    // The result of `math.floor` is the same key as the integer.
+    assert_eq!(run_test_script("t = {\"a\", \"b\", \"c\"} return t[math.floor(2.7)]"), Ok(Value::String("b".to_owned())));
+    assert_eq!(run_test_script("t = {} t[math.floor(3.5)] = 1 return #t .. tostring(t[3])"), Ok(Value::String("01".to_owned())));
+    assert_eq!(run_test_script("t = {} t[math.floor(1.5)] = 1 t[math.floor(2.5)] = 2 return #t"), Ok(Value::Number(2.0)));
+
+    // And as a for loop limit.
+    assert_eq!(run_test_script("n = 0 for i = 1, math.floor(4.9) do n = n + 1 end return n"), Ok(Value::Number(4.0)));
+
+    // And as a position in a string.
+    assert_eq!(run_test_script("return string.sub(\"hello\", math.floor(2.9))"), Ok(Value::String("ello".to_owned())));
+    assert_eq!(run_test_script("return string.sub(\"hello\", 2, math.ceil(3.1))"), Ok(Value::String("ell".to_owned())));
+}
+
+#[test]
+fn test_string_sub() {
+    assert_eq!(run_test_script("return string.sub(\"hello\", 2, 3)"), string("el"));
+    assert_eq!(run_test_script("return string.sub(\"hello\", -3)"), string("llo"));
+    assert_eq!(run_test_script("return string.sub(\"hello\", -100, 100)"), string("hello"));
+    assert_eq!(run_test_script("return string.sub(\"hello\", 0)"), string("hello"));
+    assert_eq!(run_test_script("return string.sub(\"hello\", 4, 2)"), string(""));
+    assert_eq!(run_test_script("return string.sub(12345, 2, -2)"), string("234"));
+
+    assert_eq!(run_test_script("return string.sub(\"hello\", 1.5)"),
+        Err(LuaError::BadArgument(2, "sub", "number has no integer representation".to_owned())));
+
+    // Positions are in bytes, so a non-ASCII character takes up more than
+    // one, and can't be split.
+    assert_eq!(run_test_script("return string.sub(\"héllo\", 2, 3)"), string("é"));
+    assert_eq!(run_test_script("return string.sub(\"héllo\", 4)"), string("llo"));
+    assert_eq!(run_test_script("return string.sub(\"héllo\", 2, 2)"),
+        Err(LuaError::Custom("'sub' can't split a multi-byte character".to_owned())));
+}
+
+#[test]
+fn test_string_format_specifiers() {
+    let format = |arguments: &str| run_test_script(&format!("return string.format({})", arguments));
+
+    assert_eq!(format(r#""%d-%s", 5, "x""#), string("5-x"));
+    assert_eq!(format(r#""%i|%5d|%-5d|%05d|%+d|%.3d", -3, 42, 42, -42, 7, 5"#), string("-3|   42|42   |-0042|+7|005"));
+    assert_eq!(format(r#""%d", 3.0"#), string("3"));
+
+    assert_eq!(format(r#""%.2f", 3.14159"#), string("3.14"));
+    assert_eq!(format(r#""%f|%.0f|%8.3f|%-8.1f|", 1.5, 2.5, -1, 0.25"#), string("1.500000|2|  -1.000|0.2     |"));
+    assert_eq!(format(r#""%e|%.2E", 1234.5, 0.000123"#), string("1.234500e+03|1.23E-04"));
+    assert_eq!(format(r#""%g|%g|%.3g|%G", 100000, 1000000, 3.14159, 1e-10"#), string("100000|1e+06|3.14|1E-10"));
+    assert_eq!(format(r#""%f|%5.1f", 1/0, -(0/0)"#), string("inf|  nan"));
+
+    assert_eq!(format(r#""%x|%X|%#x|%o|%04x", 255, 255, 255, 8, 10"#), string("ff|FF|0xff|10|000a"));
+    assert_eq!(format(r#""%x", -1"#), string("ffffffffffffffff"));
+
+    assert_eq!(format(r#""%q", [[say "hi"\]]"#), string(r#""say \"hi\"\\""#));
+    assert_eq!(format(r#""%q", [[a
+b]]"#), string("\"a\\\nb\""));
+    assert_eq!(format(r#""%q|%q|%q|%q", 1, 0.5, nil, true"#), string("1|0.5|nil|true"));
+    assert_eq!(format(r#""%q", {}"#), Err(LuaError::BadArgument(2, "format", "value has no literal form".to_owned())));
+
+    assert_eq!(format(r#""%d", {}"#), Err(LuaError::BadArgument(2, "format", "number expected, got table".to_owned())));
+    assert_eq!(format(r#""%f", "x""#), Err(LuaError::BadArgument(2, "format", "number expected, got string".to_owned())));
+}
+
+#[test]
+fn test_repeat_until() {
+    // Locals declared in the body are visible in the condition.
+    let x = run_test_script(r#"
+        i = 0
+        repeat
+            local done = i > 2
+            i = i + 1
+        until done
+
+        return i
+    "#);
+    assert_eq!(x, Ok(Value::Number(4.0)));
+
+    // The body always runs at least once.
+    assert_eq!(run_test_script("n = 0 repeat n = n + 1 until true return n"), Ok(Value::Number(1.0)));
+
+    // But its locals don't outlive the loop.
+    assert_eq!(run_test_script("repeat local x = 1 until x return x"), Ok(Value::Nil));
+
+    assert_eq!(run_test_script("local function f() repeat return 5 until false end return f()"), Ok(Value::Number(5.0)));
+
+    let x = run_test_script("local n <const> = 1 repeat n = 2 until true");
+    assert_eq!(x, Err(LuaError::AssignToConst("n".to_owned())));
+}
+
+#[test]
+fn test_string_len_and_rep() {
+    assert_eq!(run_test_script("return string.len(\"hello\")"), Ok(Value::Number(5.0)));
+    assert_eq!(run_test_script("return string.len(\"\")"), Ok(Value::Number(0.0)));
+    assert_eq!(run_test_script("return string.len(123)"), Ok(Value::Number(3.0)));
+    assert_eq!(run_test_script("return string.len(\"héllo\")"), Ok(Value::Number(6.0)));
+
+    assert_eq!(run_test_script("return string.rep(\"ab\", 3)"), string("ababab"));
+    assert_eq!(run_test_script("return string.rep(\"ab\", 3, \", \")"), string("ab, ab, ab"));
+    assert_eq!(run_test_script("return string.rep(\"x\", 1, \"-\")"), string("x"));
+    assert_eq!(run_test_script("return string.rep(\"x\", 0)"), string(""));
+    assert_eq!(run_test_script("return string.rep(\"x\", -1, \"-\")"), string(""));
+    assert_eq!(run_test_script("return string.rep(\"x\", 1e10)"), Err(LuaError::Custom("resulting string too large".to_owned())));
+
+    // Repeating nothing gives nothing however many times, and a result that's
+    // too large is an error that can be caught.
+    assert_eq!(run_test_script("local ok, s = pcall(string.rep, \"\", 1e12) return tostring(ok) .. s"), string("true"));
+    assert_eq!(run_test_script("return string.rep(\"\", 1e12, \"\")"), string(""));
+    assert_eq!(run_test_script("return select(2, pcall(string.rep, \"x\", 1e12, \",\"))"), string("resulting string too large"));
+
+    // Negative positions in `sub` count back from the end.
+    assert_eq!(run_test_script("return string.sub(\"hello\", -3)"), string("llo"));
+    assert_eq!(run_test_script("return string.sub(\"hello\", -4, -2)"), string("ell"));
+}
+
+#[test]
+fn test_string_upper_and_lower() {
+    assert_eq!(run_test_script("return string.upper(\"aB2\")"), string("AB2"));
+    assert_eq!(run_test_script("return string.lower(\"aB2\")"), string("ab2"));
+    assert_eq!(run_test_script("return string.upper(\"a-b, c!\")"), string("A-B, C!"));
+    assert_eq!(run_test_script("return string.upper(\"été\")"), string("éTé"));
+    assert_eq!(run_test_script("return string.lower(12)"), string("12"));
+    assert_eq!(run_test_script("return string.upper({})"),
+        Err(LuaError::BadArgument(1, "upper", "string expected, got table".to_owned())));
+}
+
+#[test]
+fn test_string_find_plain() {
+    let positions = |script: &str| run_test_script(&format!(
+        "local first, last = string.find({}) return tostring(first) .. \",\" .. tostring(last)", script));
+
+    assert_eq!(positions("\"hello world\", \"o w\", 1, true"), string("5,7"));
+    assert_eq!(positions("\"hello world\", \"world\""), string("7,11"));
+    assert_eq!(positions("\"hello world\", \"xyz\", 1, true"), string("nil,nil"));
+    assert_eq!(positions("\"a.b.c\", \".\", 1, true"), string("2,2"));
+
+    // Starting later, or counting back from the end.
+    assert_eq!(positions("\"hello hello\", \"hello\", 2"), string("7,11"));
+    assert_eq!(positions("\"hello hello\", \"hello\", -5"), string("7,11"));
+    assert_eq!(positions("\"hello hello\", \"hello\", -100"), string("1,5"));
+    assert_eq!(positions("\"hello\", \"hello\", 10"), string("nil,nil"));
+
+    // An empty pattern matches straight away.
+    assert_eq!(positions("\"abc\", \"\", 2"), string("2,1"));
+    assert_eq!(positions("\"abc\", \"\", 4"), string("4,3"));
+}
+
+#[test]
+fn test_string_patterns() {
+    assert_eq!(run_test_script("return string.match(\"order 66 of 99\", \"%d+\")"), string("66"));
+    assert_eq!(run_test_script("return string.match(\"key = value\", \"(%w+)%s*=%s*(%w+)\") "), string("key"));
+    assert_eq!(run_test_script("return select(2, string.match(\"key = value\", \"(%w+)%s*=%s*(%w+)\"))"), string("value"));
+    assert_eq!(run_test_script("return string.match(\"hello\", \"^h.-l\")"), string("hel"));
+    assert_eq!(run_test_script("return string.match(\"hello\", \"^e\")"), Ok(Value::Nil));
+    assert_eq!(run_test_script("return string.match(\"hello\", \"l+o$\")"), string("llo"));
+    assert_eq!(run_test_script("return string.match(\"  x1_y  \", \"[%a_][%w_]*\")"), string("x1_y"));
+    assert_eq!(run_test_script("return string.match(\"abc\", \"[^ab]\")"), string("c"));
+    assert_eq!(run_test_script("return string.match(\"colour\", \"colou?r\")"), string("colour"));
+    assert_eq!(run_test_script("return string.match(\"f(a(b)c)d\", \"%b()\")"), string("(a(b)c)"));
+    assert_eq!(run_test_script("return string.match(\"hello\", \"()ll()\")"), Ok(Value::Number(3.0)));
+    assert_eq!(run_test_script("return string.match(\"abcabc\", \"(abc)%1\")"), string("abc"));
+    assert_eq!(run_test_script("return string.match(\"THE (quick) fox\", \"%f[%a]%a+%f[%A]\")"), string("THE"));
+
+    assert_eq!(run_test_script("return string.find(\"a.b\", \".\", 2)"), Ok(Value::Number(2.0)));
+    assert_eq!(run_test_script("return select(3, string.find(\"x = 10\", \"(%d+)\"))"), string("10"));
+
+    let x = run_test_script(r#"
+        local words = ""
+        for word in string.gmatch("one two  three", "%a+") do
+            words = words .. "[" .. word .. "]"
+        end
+        return words
+    "#);
+    assert_eq!(x, string("[one][two][three]"));
+
+    let x = run_test_script(r#"
+        local pairs = ""
+        for key, value in string.gmatch("a=1, b=2", "(%w+)=(%w+)") do
+            pairs = pairs .. key .. value
+        end
+        return pairs
+    "#);
+    assert_eq!(x, string("a1b2"));
+
+    assert_eq!(run_test_script("return string.gsub(\"banana\", \"[aeiou]\", \"%0%0\")"), string("baanaanaa"));
+    assert_eq!(run_test_script("return select(2, string.gsub(\"banana\", \"a\", \"o\"))"), Ok(Value::Number(3.0)));
+    assert_eq!(run_test_script("return string.gsub(\"banana\", \"a\", \"o\", 2)"), string("bonona"));
+    assert_eq!(run_test_script("return string.gsub(\"hello world\", \"(%w+) (%w+)\", \"%2 %1\")"), string("world hello"));
+    assert_eq!(run_test_script("return string.gsub(\"abc\", \"\", \"-\")"), string("-a-b-c-"));
+    assert_eq!(run_test_script("return string.gsub(\"aaa\", \"^a\", \"b\")"), string("baa"));
+    assert_eq!(run_test_script("return string.gsub(\"$name is $age\", \"%$(%w+)\", { name = \"Ann\", age = 3 })"),
+        string("Ann is 3"));
+    assert_eq!(run_test_script("return string.gsub(\"1 2 3\", \"%d\", function(d) return d * 2 end)"), string("2 4 6"));
+    assert_eq!(run_test_script("return string.gsub(\"1 2\", \"%d\", function(d) return nil end)"), string("1 2"));
+
+    assert_eq!(run_test_script("return string.match(\"a\", \"%\")"),
+        Err(LuaError::Custom("malformed pattern (ends with '%')".to_owned())));
+    assert_eq!(run_test_script("return string.match(\"a\", \"[a\")"),
+        Err(LuaError::Custom("malformed pattern (missing ']')".to_owned())));
+    assert_eq!(run_test_script("return string.match(\"a\", \"(a\")"),
+        Err(LuaError::Custom("unfinished capture".to_owned())));
+    assert_eq!(run_test_script("return string.gsub(\"a\", \"a\", \"%2\")"),
+        Err(LuaError::Custom("invalid capture index %2 in replacement string".to_owned())));
+}
+
+#[test]
+fn test_xpcall() {
+    let x = run_test_script(r#"
+        local ok, a, b = xpcall(function(x, y) return x + y, x * y end, error, 2, 3)
+        return tostring(ok) .. " " .. a .. " " .. b
+    "#);
+    assert_eq!(x, Ok(Value::String("true 5 6".to_owned())));
+
+    // The handler gets the error, whatever its type, and its result replaces it.
+    let x = run_test_script(r#"
+        local ok, message = xpcall(error, function(err) return "handled " .. err.code end, { code = 7 })
+        return tostring(ok) .. " " .. message
+    "#);
+    assert_eq!(x, Ok(Value::String("false handled 7".to_owned())));
+    assert_eq!(run_test_script("return select(2, xpcall(function() return 1 + {} end, function(e) return e end))"),
+        Ok(Value::String("attempt to perform arithmetic on a table value".to_owned())));
+
+    assert_eq!(run_test_script("return select(2, xpcall(error, error, \"first\"))"),
+        Ok(Value::String("error in error handling".to_owned())));
+    assert_eq!(run_test_script("xpcall(error)"), Err(LuaError::BadArgument(2, "xpcall", "value expected".to_owned())));
+}
+
+#[test]
+fn test_string_byte_and_char() {
+    assert_eq!(run_test_script("return string.byte(\"A\")"), Ok(Value::Number(65.0)));
+    assert_eq!(run_test_script("return string.byte(\"ABC\", 2)"), Ok(Value::Number(66.0)));
+    assert_eq!(run_test_script("return string.byte(\"ABC\", -1)"), Ok(Value::Number(67.0)));
+    assert_eq!(run_test_script("return select(\"#\", string.byte(\"ABC\", 1, -1))"), Ok(Value::Number(3.0)));
+    assert_eq!(run_test_script("return select(3, string.byte(\"ABC\", 1, 10))"), Ok(Value::Number(67.0)));
+    assert_eq!(run_test_script("return select(\"#\", string.byte(\"\"))"), Ok(Value::Number(0.0)));
+
+    assert_eq!(run_test_script("return string.char(72, 105)"), Ok(Value::String("Hi".to_owned())));
+    assert_eq!(run_test_script("return string.char()"), Ok(Value::String(String::new())));
+    assert_eq!(run_test_script("return string.char(string.byte(\"xyz\", 1, 3))"), Ok(Value::String("xyz".to_owned())));
+    assert_eq!(run_test_script("return string.char(65, 256)"),
+        Err(LuaError::BadArgument(2, "char", "value out of range".to_owned())));
+    assert_eq!(run_test_script("return string.char(-1)"),
+        Err(LuaError::BadArgument(1, "char", "value out of range".to_owned())));
+
+    // Non-ASCII characters are their UTF-8 bytes, and bytes that aren't
+    // valid UTF-8 can't be made into a string.
+    assert_eq!(run_test_script("return #\"é\" .. \" \" .. string.byte(\"é\") .. \" \" .. string.byte(\"é\", 2)"),
+        Ok(Value::String("2 195 169".to_owned())));
+    assert_eq!(run_test_script("return string.char(string.byte(\"héllo\", 1, -1))"), Ok(Value::String("héllo".to_owned())));
+    assert_eq!(run_test_script("return string.char(65, 200)"),
+        Err(LuaError::BadArgument(2, "char", "invalid UTF-8".to_owned())));
+    assert_eq!(run_test_script("return string.char(195)"),
+        Err(LuaError::BadArgument(1, "char", "invalid UTF-8".to_owned())));
+}
+
+#[test]
+fn test_math_library() {
+    assert_eq!(run_test_script("return math.floor(2.7) == 2"), Ok(Value::Boolean(true)));
+    assert_eq!(run_test_script("return math.max(1, 5, 3)"), Ok(Value::Number(5.0)));
+    assert_eq!(run_test_script("return math.min(4, -2, 3)"), Ok(Value::Number(-2.0)));
+    assert_eq!(run_test_script("return math.max(7)"), Ok(Value::Number(7.0)));
+    assert_eq!(run_test_script("return math.sqrt(9) == 3"), Ok(Value::Boolean(true)));
+    assert_eq!(run_test_script("return math.abs(-2.5)"), Ok(Value::Number(2.5)));
+    assert_eq!(run_test_script("return math.huge"), Ok(Value::Number(f64::INFINITY)));
+    assert_eq!(run_test_script("return -math.huge < math.min(-1e308)"), Ok(Value::Boolean(true)));
+    assert_eq!(run_test_script("return math.pi"), Ok(Value::Number(std::f64::consts::PI)));
+
+    assert_eq!(run_test_script("return math.max()"),
+        Err(LuaError::BadArgument(1, "max", "number expected, got no value".to_owned())));
+    assert_eq!(run_test_script("return math.min(1, {})"),
+        Err(LuaError::BadArgument(2, "min", "number expected, got table".to_owned())));
+}
+
+#[test]
+fn test_ipairs() {
+    let x = run_test_script(r#"
+        local visited = ""
+        for i, v in ipairs({ 10, 20, 30 }) do
+            visited = visited .. i .. "=" .. v .. " "
+        end
+        return visited
+    "#);
+    assert_eq!(x, Ok(Value::String("1=10 2=20 3=30 ".to_owned())));
+
+    // Iteration stops at the first nil, whatever comes after it.
+    let x = run_test_script(r#"
+        local count = 0
+        for _, v in ipairs({ 1, 2, nil, 4, [6] = 6 }) do
+            count = count + 1
+        end
+        return count
+    "#);
+    assert_eq!(x, Ok(Value::Number(2.0)));
+
+    let x = run_test_script(r#"
+        local count = 0
+        for i in ipairs({}) do
+            count = count + 1
+        end
+        return count
+    "#);
+    assert_eq!(x, Ok(Value::Number(0.0)));
+
+    let x = run_test_script(r#"
+        local proxy = setmetatable({}, { __index = function(t, i) if i <= 3 then return i * i end end })
+        local sum = 0
+        for _, v in ipairs(proxy) do
+            sum = sum + v
+        end
+        return sum
+    "#);
+    assert_eq!(x, Ok(Value::Number(14.0)));
+
+    assert_eq!(run_test_script("ipairs(nil)"),
+        Err(LuaError::BadArgument(1, "ipairs", "table expected, got nil".to_owned())));
+}
+
+#[test]
+fn test_pairs() {
+    let x = run_test_script(r#"
+        local t = { a = 1, b = 2, [3] = 3 }
+        local count, sum = 0, 0
+        for k, v in pairs(t) do
+            count = count + 1
+            sum = sum + v
+            t[k] = nil
+        end
+        return count .. " " .. sum .. " " .. tostring(next(t))
+    "#);
+    assert_eq!(x, Ok(Value::String("3 6 nil".to_owned())));
+
+    // Each call starts a new traversal.
+    let x = run_test_script(r#"
+        local t = { x = 1, y = 2, 10, 20 }
+        local total = 0
+        for round = 1, 2 do
+            for k, v in pairs(t) do
+                total = total + v
+            end
+        end
+        return total
+    "#);
+    assert_eq!(x, Ok(Value::Number(66.0)));
+
+    let x = run_test_script(r#"
+        local t = setmetatable({}, { __pairs = function(t)
+            return function(_, i)
+                if i < 3 then return i + 1, "v" end
+            end, t, 0
+        end })
+
+        local keys = ""
+        for k, v in pairs(t) do
+            keys = keys .. k .. v
+        end
+        return keys
+    "#);
+    assert_eq!(x, Ok(Value::String("1v2v3v".to_owned())));
+
+    assert_eq!(run_test_script("pairs(1)"),
+        Err(LuaError::BadArgument(1, "pairs", "table expected, got number".to_owned())));
+}
+
+#[test]
+fn test_math_randomseed() {
+    let draw = r#"
+        math.randomseed(42)
+        return math.random(1, 1000000) .. " " .. math.random(1, 1000000) .. " " .. math.random()
+    "#;
+
+    let first = run_test_script(draw);
+    assert!(matches!(first, Ok(Value::String(_))));
+    assert_eq!(first, run_test_script(draw));
+
+    // Reseeding restarts the sequence, and each interpreter has its own.
+    let mut interpreter = Interpreter::new();
+    let mut other = Interpreter::new();
+    interpreter.execute("math.randomseed(7) a = math.random(1, 1000000)").expect("No errors");
+    other.execute("math.randomseed(7)").expect("No errors");
+    interpreter.execute("math.random() math.randomseed(7)").expect("No errors");
+    assert_eq!(interpreter.execute("return math.random(1, 1000000) == a"), Ok(Value::Boolean(true)));
+    assert_eq!(other.execute("return math.random(1, 1000000)"), interpreter.execute("return a"));
+
+    assert_eq!(run_test_script("math.randomseed(1) local a = math.random(0) math.randomseed(2) return a ~= math.random(0)"),
+        Ok(Value::Boolean(true)));
+}
+
+#[test]
+fn test_next() {
+    let x = run_test_script(r#"
+        local t = { a = 1 }
+        local k, v = next(t)
+        return k .. v .. tostring(next(t, k))
+    "#);
+    assert_eq!(x, Ok(Value::String("a1nil".to_owned())));
+    assert_eq!(run_test_script("return next({})"), Ok(Value::Nil));
+
+    // A traversal by hand visits every key once.
+    let x = run_test_script(r#"
+        local t = { 1, 2, 3, x = 4, y = 5, [2.5] = 6 }
+        local sum, count = 0, 0
+        local k, v = next(t)
+        repeat
+            sum = sum + v
+            count = count + 1
+            k, v = next(t, k)
+        until k == nil
+        return sum * 10 + count
+    "#);
+    assert_eq!(x, Ok(Value::Number(216.0)));
+
+    assert_eq!(run_test_script("return next({}, \"missing\")"),
+        Err(LuaError::Custom("invalid key to 'next'".to_owned())));
+    assert_eq!(run_test_script("return next(nil)"),
+        Err(LuaError::BadArgument(1, "next", "table expected, got nil".to_owned())));
+}
+
+#[test]
+fn test_os_time_and_clock() {
+    assert_eq!(run_test_script("return os.time() > 0"), Ok(Value::Boolean(true)));
+    assert_eq!(run_test_script("return math.floor(os.time()) == os.time()"), Ok(Value::Boolean(true)));
+
+    let x = run_test_script(r#"
+        local previous = os.clock()
+        for i = 1, 100 do
+            local now = os.clock()
+            if now < previous then
+                return false
+            end
+            previous = now
+        end
+        return previous >= 0
+    "#);
+    assert_eq!(x, Ok(Value::Boolean(true)));
+
+    assert_eq!(run_test_script("return os.time({ year = 2000, month = 1, day = 1 })"),
+        Err(LuaError::BadArgument(1, "time", "date tables aren't supported".to_owned())));
+}
+
+#[test]
+fn test_any_value_as_index() {
+    let x = run_test_script(r#"
+        local key, f = {}, function() end
+        local t = { [true] = "yes", [false] = "no", [key] = "table", [f] = "function", [type] = "native" }
+        t[{}] = "other"
+        return t[true] .. t[false] .. t[key] .. t[f] .. tostring(t[{}]) .. t[type]
+    "#);
+    assert_eq!(x, Ok(Value::String("yesnotablefunctionnilnative".to_owned())));
+
+    // Keys are given back as the same value by `next`.
+    let x = run_test_script(r#"
+        local key = {}
+        local t = { [key] = 1 }
+        return next(t) == key
+    "#);
+    assert_eq!(x, Ok(Value::Boolean(true)));
+
+    // Reading with nil or NaN gives nil, but they can't be set.
+    assert_eq!(run_test_script("return ({})[nil]"), Ok(Value::Nil));
+    assert_eq!(run_test_script("return ({})[0/0]"), Ok(Value::Nil));
+    assert_eq!(run_test_script("return setmetatable({}, { __index = function(t, k) return k == nil end })[nil]"),
+        Ok(Value::Boolean(true)));
+    assert_eq!(run_test_script("t = {} t[nil] = 1"), Err(LuaError::InvalidTableIndex(Value::Nil)));
+    assert_eq!(LuaError::InvalidTableIndex(Value::Nil).to_string(), "index is nil");
+    assert_eq!(run_test_script("return { [0/0] = 1 }").map_err(|error| error.to_string()), Err("index is NaN".to_owned()));
+    assert_eq!(run_test_script("rawset({}, nil, 1)"), Err(LuaError::InvalidTableIndex(Value::Nil)));
+}
+
+#[test]
+fn test_close_metamethod() {
+    let x = run_test_script(r#"
+        log = ""
+        local function closable(name)
+            return setmetatable({}, { __close = function(value, err) log = log .. name .. tostring(err) .. " " end })
+        end
+
+        do
+            local a <close> = closable("a")
+            local b <close> = closable("b")
+            local c <close> = false
+            log = log .. "body "
+        end
+        return log
+    "#);
+    assert_eq!(x, Ok(Value::String("body bnil anil ".to_owned())));
+
+    // Values are closed when returning from a function, or when looping.
+    let x = run_test_script(r#"
+        log = ""
+        local mt = { __close = function() log = log .. "closed " end }
+        local function f()
+            local x <close> = setmetatable({}, mt)
+            return "returned "
+        end
+
+        local returned = f()
+        log = log .. returned
+        for i = 1, 2 do
+            local y <close> = setmetatable({}, mt)
+        end
+
+        local n = 0
+        repeat
+            local z <close> = setmetatable({}, mt)
+            n = n + 1
+        until z and n == 1
+        return log
+    "#);
+    assert_eq!(x, Ok(Value::String("closed returned closed closed closed ".to_owned())));
+
+    // An error still closes the value, which is given the error, and the
+    // error carries on afterwards.
+    let x = run_test_script(r#"
+        local seen
+        local ok, err = pcall(function()
+            local x <close> = setmetatable({}, { __close = function(_, err) seen = err end })
+            error("boom")
+        end)
+        return seen .. " " .. tostring(ok) .. " " .. err
+    "#);
+    assert_eq!(x, Ok(Value::String("chunk:5: boom false chunk:5: boom".to_owned())));
+
+    // An error while closing replaces the one the block exited with.
+    let x = run_test_script(r#"
+        local ok, err = pcall(function()
+            local x <close> = setmetatable({}, { __close = function() error("in close") end })
+        end)
+        return err
+    "#);
+    assert_eq!(x, Ok(Value::String("chunk:3: in close".to_owned())));
 }